@@ -0,0 +1,259 @@
+#![deny(warnings)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+use base64::{Engine,engine::general_purpose};
+use serde_json::{Value,json};
+
+use crate::models::{RemoteData,RemoteResultType};
+use crate::content_type::GetHeaderValueString;
+
+#[derive(Debug)]
+pub enum CacheError{
+    NotFound,
+    InvalidEntry,
+    IOError(std::io::Error)
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self{
+            CacheError::NotFound => write!(f, "Cache entry not found"),
+            CacheError::InvalidEntry => write!(f, "Cache entry could not be parsed"),
+            CacheError::IOError(e) => write!(f, "Cache could not be accessed: {e}")
+        }
+    }
+}
+
+impl From<std::io::Error> for CacheError{
+    fn from(e: std::io::Error) -> Self{
+        match e.kind(){
+            std::io::ErrorKind::NotFound => CacheError::NotFound,
+            _ => CacheError::IOError(e)
+        }
+    }
+}
+
+#[derive(Debug,Clone,Default)]
+pub struct CacheValidators{
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+impl CacheValidators{
+    pub fn from_headers(headers: &hyper::HeaderMap) -> Self{
+        CacheValidators{
+            etag: headers.get_as_string("ETag").map(|s| s.to_string()),
+            last_modified: headers.get_as_string("Last-Modified").map(|s| s.to_string())
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct CacheEntry{
+    pub data: RemoteData,
+    pub timestamp: SystemTime,
+    pub uri: String,
+    pub validators: CacheValidators,
+    // Upstream response headers that are passed on to the client
+    pub headers: hyper::HeaderMap
+}
+
+impl CacheEntry{
+    pub fn new(data: RemoteData, uri: &hyper::Uri, headers: &hyper::HeaderMap) -> Self{
+        CacheEntry{
+            data,
+            timestamp: SystemTime::now(),
+            uri: uri.to_string(),
+            validators: CacheValidators::from_headers(headers),
+            headers: hyper::HeaderMap::new()
+        }
+    }
+    pub fn with_headers(mut self, headers: hyper::HeaderMap) -> Self{
+        self.headers = headers;
+        self
+    }
+    pub fn age(&self) -> Duration{
+        SystemTime::now().duration_since(self.timestamp).unwrap_or(Duration::ZERO)
+    }
+    // Entries without ttl never expire
+    pub fn is_fresh(&self, ttl: Option<Duration>) -> bool{
+        match ttl{
+            Some(t) => self.age() < t,
+            None => true
+        }
+    }
+    fn to_json(&self) -> Value{
+        // Every header is a list so that repeated headers keep all of their values
+        let mut headers = serde_json::Map::new();
+        for name in self.headers.keys(){
+            let values : Vec<Value> = self.headers.get_all(name).iter()
+                .filter_map(|v| v.to_str().ok())
+                .map(Value::from)
+                .collect();
+            headers.insert(name.to_string(),Value::Array(values));
+        }
+        let seconds = self.timestamp.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+        json!({
+            "kind": self.data.kind.as_str(),
+            "timestamp": seconds,
+            "uri": self.uri,
+            "etag": self.validators.etag,
+            "last_modified": self.validators.last_modified,
+            "headers": headers,
+            "data": general_purpose::STANDARD.encode(self.data.data_bytes())
+        })
+    }
+    fn from_json(input: &Value) -> Result<Self,CacheError>{
+        let kind = match input.get("kind").and_then(|v| v.as_str()).and_then(RemoteResultType::from_str){
+            Some(k) => k,
+            None => return Err(CacheError::InvalidEntry)
+        };
+        let timestamp = match input.get("timestamp").and_then(|v| v.as_u64()){
+            Some(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            None => return Err(CacheError::InvalidEntry)
+        };
+        let uri = match input.get("uri").and_then(|v| v.as_str()){
+            Some(s) => s.to_string(),
+            None => return Err(CacheError::InvalidEntry)
+        };
+        let data = match input.get("data").and_then(|v| v.as_str()){
+            Some(s) => match general_purpose::STANDARD.decode(s){
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(CacheError::InvalidEntry)
+                }
+            },
+            None => return Err(CacheError::InvalidEntry)
+        };
+        let validators = CacheValidators{
+            etag: input.get("etag").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            last_modified: input.get("last_modified").and_then(|v| v.as_str()).map(|s| s.to_string())
+        };
+        let mut headers = hyper::HeaderMap::new();
+        if let Some(map) = input.get("headers").and_then(|v| v.as_object()){
+            for (name,value) in map.iter(){
+                let values = match value.as_array(){
+                    Some(list) => list,
+                    None => return Err(CacheError::InvalidEntry)
+                };
+                let name = match hyper::header::HeaderName::from_bytes(name.as_bytes()){
+                    Ok(n) => n,
+                    Err(_) => return Err(CacheError::InvalidEntry)
+                };
+                for value in values.iter(){
+                    match value.as_str().and_then(|v| hyper::header::HeaderValue::from_str(v).ok()){
+                        Some(v) => { headers.append(&name,v); },
+                        None => return Err(CacheError::InvalidEntry)
+                    }
+                }
+            }
+        }
+        Ok(CacheEntry{
+            data: RemoteData::new(kind,data),
+            timestamp,
+            uri,
+            validators,
+            headers
+        })
+    }
+}
+
+// Concurrent writes of the same entry each get their own temporary file
+static WRITE_SEQUENCE : AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub struct DiskCache{
+    dir: PathBuf
+}
+
+impl DiskCache{
+    pub fn new(dir: &str) -> Self{
+        DiskCache{ dir: PathBuf::from(dir) }
+    }
+    pub fn dir(&self) -> &PathBuf{
+        &self.dir
+    }
+    // Resource names come from config keys so they are escaped before used as filenames,
+    // other bytes become ".xx" so that e.g. "a/b" and "a_b" never share a file
+    fn path_for(&self, name: &str) -> PathBuf{
+        let mut filename = String::with_capacity(name.len());
+        for byte in name.bytes(){
+            match byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'{
+                true => filename.push(byte as char),
+                false => filename.push_str(&format!(".{byte:02x}"))
+            }
+        }
+        self.dir.join(format!("{filename}.json"))
+    }
+    pub async fn store(&self, name: &str, entry: &CacheEntry) -> Result<(),CacheError>{
+        use tokio::io::AsyncWriteExt;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut value = entry.to_json();
+        value["name"] = Value::from(name);
+        let bytes = match serde_json::to_vec(&value){
+            Ok(b) => b,
+            Err(_) => return Err(CacheError::InvalidEntry)
+        };
+        // Written to a temporary file first so that readers never see a partly written entry
+        let path = self.path_for(name);
+        let temporary = path.with_extension(format!("{}.{}.tmp",std::process::id(),WRITE_SEQUENCE.fetch_add(1,Ordering::Relaxed)));
+        let written = async {
+            let mut file = tokio::fs::File::create(&temporary).await?;
+            file.write_all(&bytes).await?;
+            file.flush().await?;
+            tokio::fs::rename(&temporary,&path).await
+        }.await;
+        if let Err(e) = written{
+            let _ = tokio::fs::remove_file(&temporary).await;
+            return Err(e.into())
+        }
+        Ok(())
+    }
+    pub fn load(&self, name: &str) -> Result<CacheEntry,CacheError>{
+        let bytes = std::fs::read(self.path_for(name))?;
+        match serde_json::from_slice::<Value>(&bytes){
+            Ok(value) => CacheEntry::from_json(&value),
+            Err(_) => Err(CacheError::InvalidEntry)
+        }
+    }
+    pub fn list(&self) -> Result<Vec<(String,CacheEntry)>,CacheError>{
+        let mut entries = vec![];
+        for item in std::fs::read_dir(&self.dir)?{
+            let path = item?.path();
+            if path.extension().is_none_or(|ext| ext != "json"){
+                continue
+            }
+            let value = match std::fs::read(&path).ok().and_then(|b| serde_json::from_slice::<Value>(&b).ok()){
+                Some(v) => v,
+                None => {
+                    eprintln!("Ignoring unreadable cache file: {}",path.display());
+                    continue
+                }
+            };
+            let name = value.get("name").and_then(|v| v.as_str()).unwrap_or("<unknown>").to_string();
+            match CacheEntry::from_json(&value){
+                Ok(entry) => entries.push((name,entry)),
+                Err(e) => eprintln!("{}: {e}",path.display())
+            }
+        }
+        entries.sort_by(|a,b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+    pub fn remove(&self, name: &str) -> Result<(),CacheError>{
+        std::fs::remove_file(self.path_for(name))?;
+        Ok(())
+    }
+    pub fn clear(&self) -> Result<usize,CacheError>{
+        let mut count = 0;
+        for item in std::fs::read_dir(&self.dir)?{
+            let path = item?.path();
+            if path.extension().is_some_and(|ext| ext == "json"){
+                std::fs::remove_file(&path)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
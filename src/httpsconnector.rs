@@ -1,19 +1,33 @@
 #![deny(warnings)]
 use bytes::Bytes;
-use http_body_util::{Full,Empty,BodyExt,Collected};
+use http_body_util::{Full,BodyExt};
 use hyper_tls::HttpsConnector;
 use hyper::{body::Buf,Request};
-use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use hyper_util::{client::legacy::{Client,connect::HttpConnector}, rt::{TokioExecutor,TokioTimer}};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::models::{RemoteResult,RemoteData,JSONSerializeType,JSONKind};
+use crate::models::{RemoteResult,RemoteData,RemoteResultType,JSONSerializeType,JSONKind};
 use crate::schemers::validator::Validator;
 use crate::settings::resource::{ResourceMethod,RequestCredentials};
 use crate::settings::header::HeaderSet;
+use crate::settings::retry::{ResourceTimeouts,RetryPolicy};
+use crate::settings::limits::UpstreamLimiter;
+use crate::settings::transform::Transform;
+use crate::settings::pagination::Pagination;
+use crate::settings::oauth::OAuth2Credentials;
+use crate::settings::login::{SessionAuth,LoginResponse};
+use crate::settings::cookiejar::CookieJar;
+use crate::settings::tls::TlsOptions;
+use crate::settings::proxy::ProxySettings;
+use crate::settings::resolve::ResolveOverrides;
+use crate::upstreamconnector::{UpstreamConnector,proxy_for};
 
 pub type ConnectionResult<T> = Result<T, ConnectionError>;
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub enum ConnectionError{
     NotFound,
     InvalidURI,
@@ -22,7 +36,26 @@ pub enum ConnectionError{
     InvalidUTF8,
     InvalidRequest,
     InternalError,
-    NotSupported
+    NotSupported,
+    Timeout,
+    BadStatus(hyper::StatusCode),
+    CircuitOpen,
+    RateLimited,
+    // Property path and reason of a request body that failed schema validation
+    InvalidBody(String,String),
+    BodyTooLarge,
+    AuthFailed
+}
+
+impl ConnectionError{
+    // Errors that indicate the upstream itself is unhealthy
+    pub fn is_upstream_failure(&self) -> bool{
+        match self{
+            ConnectionError::NotFound | ConnectionError::Timeout => true,
+            ConnectionError::BadStatus(status) => status.is_server_error() || *status == hyper::StatusCode::TOO_MANY_REQUESTS,
+            _ => false
+        }
+    }
 }
 
 impl std::fmt::Display for ConnectionError {
@@ -35,7 +68,14 @@ impl std::fmt::Display for ConnectionError {
             ConnectionError::InvalidUTF8 => write!(f, "Invalid UTF8"),
             ConnectionError::InvalidRequest => write!(f, "Request could not be constructed"),
             ConnectionError::InternalError => write!(f, "Server found itself from an unexpected state"),
-            ConnectionError::NotSupported => write!(f, "Requested data model is currently not supported")
+            ConnectionError::NotSupported => write!(f, "Requested data model is currently not supported"),
+            ConnectionError::Timeout => write!(f, "Upstream request timed out"),
+            ConnectionError::BadStatus(status) => write!(f, "Upstream responded with status {status}"),
+            ConnectionError::CircuitOpen => write!(f, "Upstream circuit is open"),
+            ConnectionError::RateLimited => write!(f, "Upstream request limit exceeded"),
+            ConnectionError::InvalidBody(_,reason) => write!(f, "Request body is invalid: {reason}"),
+            ConnectionError::BodyTooLarge => write!(f, "Request body is too large"),
+            ConnectionError::AuthFailed => write!(f, "Upstream access token could not be acquired")
         }
     }
 }
@@ -52,59 +92,328 @@ fn request_builder(request_init: &RequestOptions<'_>,method: hyper::Method) -> h
     for header in request_init.request_headers.headers().iter(){
         builder = builder.header(header.name().as_str(), header.value().to_value_str());
     }
+    for (name,value) in request_init.forwarded_headers.iter(){
+        builder = builder.header(name, value);
+    }
+    if let Some(content_type) = request_init.content_type{
+        if builder.headers_ref().is_some_and(|h| !h.contains_key(http::header::CONTENT_TYPE)){
+            builder = builder.header(http::header::CONTENT_TYPE, content_type);
+        }
+    }
     builder
 }
 
-pub async fn request_get_resource(request_init: RequestOptions<'_>) -> ConnectionResult<Collected<bytes::Bytes>>{
-    let request = match request_builder(&request_init, hyper::Method::GET).body(Empty::new()){
-        Ok(req) => req,
-        Err(_) => return Err(ConnectionError::InvalidRequest)
-    };
-    let https = HttpsConnector::new();
-    let client = Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(https);
-    let res = match client.request(request).await{
-        Ok(r) => r,
-        Err(e) => {
-            println!("{}",e);
-            return Err(ConnectionError::NotFound)
+#[derive(Clone)]
+pub struct HttpClient{
+    inner: Client<HttpsConnector<UpstreamConnector>,Full<Bytes>>,
+    proxy: Option<Arc<ProxySettings>>,
+    resolve: Arc<ResolveOverrides>
+}
+
+impl HttpClient{
+    pub async fn request(&self, mut request: Request<Full<Bytes>>) -> Result<hyper::Response<hyper::body::Incoming>,hyper_util::client::legacy::Error>{
+        // Plain HTTP is forwarded by the proxy, so the request itself carries the proxy credentials
+        if request.uri().scheme_str() == Some("http"){
+            if let Some(auth) = proxy_for(self.proxy.as_deref(),&self.resolve,request.uri()).and_then(|p| p.authorization.clone()){
+                request.headers_mut().insert(http::header::PROXY_AUTHORIZATION,auth);
+            }
         }
-    };
+        self.inner.request(request).await
+    }
+}
+
+// Resources with equal connector settings share the same client and its connection pool
+#[derive(Debug,Clone,Hash,PartialEq,Eq)]
+pub struct ConnectorProfile{
+    connect_timeout: Option<Duration>,
+    tls: Option<TlsOptions>
+}
+
+impl ConnectorProfile{
+    pub fn new(timeouts: &ResourceTimeouts, tls: Option<&TlsOptions>) -> Self{
+        ConnectorProfile{ connect_timeout: timeouts.connect, tls: tls.cloned() }
+    }
+    fn build_connector(&self, proxy: Option<Arc<ProxySettings>>, resolve: Arc<ResolveOverrides>) -> HttpsConnector<UpstreamConnector>{
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(self.connect_timeout);
+        let http = UpstreamConnector::new(http,proxy,resolve);
+        match self.tls.as_ref().map(|t| t.connector()){
+            Some(Ok(tls)) => HttpsConnector::from((http,tokio_native_tls::TlsConnector::from(tls))),
+            // TLS settings are validated while parsing, so this is not expected to happen
+            Some(Err(e)) => {
+                eprintln!("Custom TLS settings could not be applied, using the system defaults: {e}");
+                HttpsConnector::new_with_connector(http)
+            },
+            None => HttpsConnector::new_with_connector(http)
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct PoolOptions{
+    pub max_idle_per_host: usize,
+    pub idle_timeout: Option<Duration>,
+    pub proxy: Option<Arc<ProxySettings>>,
+    pub resolve: Arc<ResolveOverrides>
+}
 
-    if !res.status().is_success(){
-        return Err(ConnectionError::NotFound)
+impl Default for PoolOptions{
+    fn default() -> Self{
+        PoolOptions{ max_idle_per_host: 8, idle_timeout: Some(Duration::from_secs(90)), proxy: None, resolve: Arc::new(ResolveOverrides::default()) }
     }
+}
+
+// Index of a pooled client, resources get theirs when the settings are parsed
+pub type ClientId = usize;
+
+pub struct ClientPool{
+    options: PoolOptions,
+    // The first client uses the default connector settings
+    clients: Vec<HttpClient>,
+    ids: HashMap<ConnectorProfile,ClientId>
+}
+
+impl ClientPool{
+    pub fn new(options: PoolOptions) -> Self{
+        let profile = ConnectorProfile::new(&ResourceTimeouts::default(),None);
+        let default = ClientPool::build_client(&options,&profile);
+        ClientPool{ options, clients: vec![default], ids: HashMap::from([(profile,0)]) }
+    }
+    fn build_client(options: &PoolOptions, profile: &ConnectorProfile) -> HttpClient{
+        let inner = Client::builder(TokioExecutor::new())
+            .pool_max_idle_per_host(options.max_idle_per_host)
+            .pool_idle_timeout(options.idle_timeout)
+            .pool_timer(TokioTimer::new())
+            .build(profile.build_connector(options.proxy.clone(),options.resolve.clone()));
+        HttpClient{ inner, proxy: options.proxy.clone(), resolve: options.resolve.clone() }
+    }
+    pub fn register(&mut self, profile: ConnectorProfile) -> ClientId{
+        if let Some(id) = self.ids.get(&profile){
+            return *id
+        }
+        self.clients.push(ClientPool::build_client(&self.options,&profile));
+        self.ids.insert(profile,self.clients.len() - 1);
+        self.clients.len() - 1
+    }
+    pub fn client_for(&self, id: ClientId) -> &HttpClient{
+        match self.clients.get(id){
+            Some(client) => client,
+            None => &self.clients[0]
+        }
+    }
+}
 
-    let body = match res.collect().await{
-      Ok(s) => s,
-      Err(_) => return Err(ConnectionError::NotFound)  
+async fn send_once(client: &HttpClient, request: Request<Full<Bytes>>, timeout: Option<Duration>, cookie_jar: Option<&CookieJar>) -> ConnectionResult<RemoteResponse>{
+    let uri = request.uri().clone();
+    let exchange = async {
+        let res = match client.request(request).await{
+            Ok(r) => r,
+            Err(e) => {
+                println!("{}",e);
+                return Err(ConnectionError::NotFound)
+            }
+        };
+        // Cookies are stored from error responses too, e.g. a 401 may end the session
+        if let Some(jar) = cookie_jar{
+            jar.store(&uri, res.headers()).await;
+        }
+        if !res.status().is_success(){
+            return Err(ConnectionError::BadStatus(res.status()))
+        }
+        let (parts,incoming) = res.into_parts();
+        let body = match incoming.collect().await{
+          Ok(s) => s.to_bytes(),
+          Err(_) => return Err(ConnectionError::NotFound)  
+        };
+        Ok(RemoteResponse{ headers: parts.headers, body })
     };
-    Ok(body)
+    match timeout{
+        Some(t) => match tokio::time::timeout(t,exchange).await{
+            Ok(res) => res,
+            Err(_) => Err(ConnectionError::Timeout)
+        },
+        None => exchange.await
+    }
 }
 
-pub async fn request_post_resource(request_init: RequestOptions<'_>) -> ConnectionResult<Collected<bytes::Bytes>>{
-    let request = match request_builder(&request_init, hyper::Method::POST).body(Full::new(request_init.bytes())){
+// Returns the cached access token or fetches a new one from the token endpoint
+async fn access_token(request_init: &RequestOptions<'_>, oauth: &OAuth2Credentials) -> ConnectionResult<String>{
+    let mut guard = oauth.lock().await;
+    if let Some(token) = guard.as_ref().filter(|t| t.is_valid()){
+        return Ok(token.value.clone())
+    }
+    oauth.prepare_secret().await;
+    let request = match oauth.token_request(request_init.user_agent){
         Ok(req) => req,
-        Err(_) => return Err(ConnectionError::InvalidRequest)
+        Err(e) => {
+            eprintln!("Token request could not be constructed: {e}");
+            return Err(ConnectionError::AuthFailed)
+        }
     };
-    let https = HttpsConnector::new();
-    let client =  Client::builder(TokioExecutor::new()).build::<_, Full<Bytes>>(https);
-    let res = match client.request(request).await{
-        Ok(r) => r,
+    let response = match send_once(request_init.client, request, request_init.timeouts.request, None).await{
+        Ok(res) => res,
         Err(e) => {
-            println!("{}",e);
-            return Err(ConnectionError::NotFound)
+            eprintln!("Token endpoint failed: {e}");
+            return Err(ConnectionError::AuthFailed)
         }
     };
+    match OAuth2Credentials::parse_token(&response.body){
+        Some(token) => {
+            let value = token.value.clone();
+            *guard = Some(token);
+            Ok(value)
+        },
+        None => {
+            eprintln!("Token endpoint returned an invalid response");
+            Err(ConnectionError::AuthFailed)
+        }
+    }
+}
 
-    if !res.status().is_success(){
-        return Err(ConnectionError::NotFound)
+// Returns the current session or logs in through the login resource
+async fn session_value(session: &SessionLogin<'_>) -> ConnectionResult<String>{
+    let mut guard = session.auth.lock().await;
+    if let Some(current) = guard.as_ref().and_then(|response| session.auth.establish(response)).filter(|s| s.is_valid()){
+        return Ok(current.value)
     }
+    let response = match Box::pin(send_with_policy(&session.login, session.login.method.as_method())).await{
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Login through {} failed: {e}",session.auth.login);
+            return Err(ConnectionError::AuthFailed)
+        }
+    };
+    let response = LoginResponse::new(response.headers,response.body);
+    match session.auth.establish(&response){
+        Some(established) => {
+            *guard = Some(response);
+            Ok(established.value)
+        },
+        None => {
+            eprintln!("Login response of {} did not contain a session",session.auth.login);
+            Err(ConnectionError::AuthFailed)
+        }
+    }
+}
 
-    let body = match res.collect().await{
-      Ok(s) => s,
-      Err(_) => return Err(ConnectionError::NotFound)  
+// Adds credentials that are acquired at request time, returns them so that they can be revoked when rejected
+async fn authorize(request_init: &RequestOptions<'_>, request: &mut Request<Full<Bytes>>) -> ConnectionResult<Option<String>>{
+    let (header,value,credential) = match (request_init.oauth,&request_init.session){
+        (Some(oauth),_) => {
+            let token = access_token(request_init, oauth).await?;
+            (oauth.header(),format!("Bearer {token}"),token)
+        },
+        (None,Some(session)) => {
+            let value = session_value(session).await?;
+            (session.auth.header(),value.clone(),value)
+        },
+        (None,None) => return Ok(None)
+    };
+    let name = match http::HeaderName::from_bytes(header.as_bytes()){
+        Ok(name) => name,
+        Err(_) => return Err(ConnectionError::AuthFailed)
+    };
+    // A session cookie is sent along with the cookies of the jar in a single header
+    let value = match (name == http::header::COOKIE, request.headers().get(&name).and_then(|v| v.to_str().ok())){
+        (true,Some(existing)) => format!("{existing}; {value}"),
+        (_,_) => value
     };
-    Ok(body)
+    match http::HeaderValue::from_str(&value){
+        Ok(value) => request.headers_mut().insert(name, value),
+        Err(_) => return Err(ConnectionError::AuthFailed)
+    };
+    Ok(Some(credential))
+}
+
+async fn revoke(request_init: &RequestOptions<'_>, credential: &str){
+    if let Some(oauth) = request_init.oauth{
+        oauth.invalidate(credential).await;
+    }
+    if let Some(session) = &request_init.session{
+        session.auth.invalidate(credential).await;
+    }
+}
+
+// Sends the request according to the retry policy and timeouts of the resource.
+async fn send_with_policy(request_init: &RequestOptions<'_>, method: hyper::Method) -> ConnectionResult<RemoteResponse>{
+    let policy = request_init.retry;
+    let idempotent = method.is_idempotent();
+    let attempts = policy.attempts_for(idempotent);
+    let body = request_init.bytes();
+    let run = async {
+        let mut attempt = 1;
+        let mut reauthorized = false;
+        loop{
+            let mut request = match request_builder(request_init, method.clone()).body(Full::new(body.clone())){
+                Ok(req) => req,
+                Err(_) => return Err(ConnectionError::InvalidRequest)
+            };
+            if let Some(cookies) = request_init.cookie_jar.and_then(|jar| jar.cookie_header(&request_init.uri)){
+                match http::HeaderValue::from_str(&cookies){
+                    Ok(value) => { request.headers_mut().insert(http::header::COOKIE, value); },
+                    Err(_) => return Err(ConnectionError::InvalidRequest)
+                }
+            }
+            let credential = authorize(request_init, &mut request).await?;
+            let permit = match request_init.limiter{
+                Some(limiter) => match limiter.acquire().await{
+                    Ok(permit) => permit,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        return Err(ConnectionError::RateLimited)
+                    }
+                },
+                None => None
+            };
+            let result = send_once(request_init.client, request, request_init.timeouts.request, request_init.cookie_jar).await;
+            drop(permit);
+            // Tokens and sessions may end before their expiry, they are renewed once without counting as an attempt
+            if let (Some(credential), Err(ConnectionError::BadStatus(hyper::StatusCode::UNAUTHORIZED))) = (&credential, &result){
+                if !reauthorized{
+                    println!("Upstream rejected the credentials, requesting new ones");
+                    revoke(request_init, credential).await;
+                    reauthorized = true;
+                    continue
+                }
+            }
+            let retryable = match &result{
+                Ok(_) => false,
+                Err(ConnectionError::BadStatus(status)) => policy.is_retryable_status(*status),
+                Err(ConnectionError::NotFound) | Err(ConnectionError::Timeout) => true,
+                Err(_) => false
+            };
+            if !retryable || attempt >= attempts{
+                return result
+            }
+            let delay = policy.backoff_for(attempt);
+            println!("Upstream request failed, retrying in {}ms (attempt {}/{})",delay.as_millis(),attempt + 1,attempts);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    };
+    match request_init.timeouts.total{
+        Some(t) => match tokio::time::timeout(t,run).await{
+            Ok(res) => res,
+            Err(_) => Err(ConnectionError::Timeout)
+        },
+        None => run.await
+    }
+}
+
+pub async fn request_resource(request_init: RequestOptions<'_>) -> ConnectionResult<RemoteResponse>{
+    send_with_policy(&request_init, request_init.method.as_method()).await
+}
+
+pub struct RemoteResponse{
+    pub headers: hyper::HeaderMap,
+    pub body: Bytes
+}
+
+#[derive(Debug,Clone)]
+pub struct UpstreamData{
+    pub data: RemoteData,
+    pub headers: hyper::HeaderMap
 }
 
 pub struct RequestOptions<'a>{
@@ -113,13 +422,29 @@ pub struct RequestOptions<'a>{
     pub credentials: Option<RequestCredentials>,
     pub method: &'a ResourceMethod,
     pub request_headers: &'a HeaderSet,
-    pub body: Option<Bytes>
+    pub forwarded_headers: Vec<(http::HeaderName,http::HeaderValue)>,
+    pub body: Option<Bytes>,
+    pub content_type: Option<&'a str>,
+    pub timeouts: &'a ResourceTimeouts,
+    pub retry: &'a RetryPolicy,
+    pub client: &'a HttpClient,
+    pub limiter: Option<&'a UpstreamLimiter>,
+    pub pagination: Option<&'a Pagination>,
+    pub oauth: Option<&'a OAuth2Credentials>,
+    pub session: Option<SessionLogin<'a>>,
+    pub cookie_jar: Option<&'a CookieJar>
+}
+
+// Session of a resource and the request that establishes it
+pub struct SessionLogin<'a>{
+    pub auth: &'a SessionAuth,
+    pub login: Box<RequestOptions<'a>>
 }
 
 impl<'a> RequestOptions<'a>{
-    fn bytes(self) -> Bytes {
-        match self.body{
-            Some(bytes) => bytes,
+    fn bytes(&self) -> Bytes {
+        match &self.body{
+            Some(bytes) => bytes.clone(),
             None => Bytes::new()
         }
     }
@@ -136,34 +461,101 @@ pub fn validate_response(data_buffer: impl Buf, validator: &Validator) -> Result
     }
 }
 
-pub async fn request_optionally_validated_json(request_init: RequestOptions<'_>, data_kind: JSONKind, validator: Option<&Validator>) -> ConnectionResult<RemoteData>{
-    let response = match request_json(request_init).await{
-        Ok(res) => res.aggregate(),
+fn apply_transform(data: RemoteData, transform: &Transform) -> Result<RemoteData,ConnectionError>{
+    let value : serde_json::Value = match serde_json::from_slice(data.data_bytes()){
+        Ok(v) => v,
         Err(_) => return Err(ConnectionError::InvalidJSON)
     };
-    match (validator, data_kind){
-        (Some(schema), JSONKind::UntypedValue) => validate_response(response,schema),
+    match serde_json::to_vec_pretty(&transform.apply(value)){
+        Ok(bytes) => Ok(RemoteData::new(data.kind,bytes)),
+        Err(_) => Err(ConnectionError::InvalidJSON)
+    }
+}
+
+pub async fn request_optionally_validated_json(request_init: RequestOptions<'_>, data_kind: JSONKind, validator: Option<&Validator>, transform: Option<&Transform>) -> ConnectionResult<UpstreamData>{
+    let returns_empty = !matches!(request_init.method,ResourceMethod::Get);
+    let (headers,response) = match request_json(request_init).await{
+        Ok(res) => (res.headers, res.body),
+        Err(e) => return Err(e)
+    };
+    // Modifying requests are often answered with an empty body, e.g. 204 No Content
+    if returns_empty && !response.has_remaining(){
+        return Ok(UpstreamData{ data: RemoteData::new(RemoteResultType::RemoteJSON(data_kind),vec![]), headers })
+    }
+    let data = match (validator, data_kind){
+        (Some(schema), JSONKind::UntypedValue) => validate_response(response,schema)?,
         (_,kind) => match RemoteResult::json(response,&kind,&JSONSerializeType::Pretty){
-            Ok(blob) => Ok(blob),
+            Ok(blob) => blob,
             Err(e) => {
                 println!("{}",e);
-                Err(ConnectionError::InvalidJSON)
+                return Err(ConnectionError::InvalidJSON)
             }
         }
-    }
+    };
+    let data = match transform{
+        Some(t) => apply_transform(data,t)?,
+        None => data
+    };
+    Ok(UpstreamData{ data, headers })
 }
 
-pub async fn request_json(request_init: RequestOptions<'_>) -> ConnectionResult<Collected<Bytes>>{
+pub async fn request_json(request_init: RequestOptions<'_>) -> ConnectionResult<RemoteResponse>{
+    if let Some(pagination) = request_init.pagination{
+        return request_pages(request_init, pagination).await
+    }
+    match request_resource(request_init).await{
+        Ok(s) => Ok(s),
+        Err(e) => return Err(e)
+    }
     
-    match &request_init.method{
-        ResourceMethod::Get => match request_get_resource(request_init).await{
-            Ok(s) => Ok(s),
-            Err(e) => return Err(e)
-        },
-        ResourceMethod::Post => match request_post_resource(request_init).await{
-            Ok(s) => Ok(s),
-            Err(e) => return Err(e)
+}
+
+// Follows the pages and merges their items into the first page, headers are taken from the first page
+async fn request_pages(mut request_init: RequestOptions<'_>, pagination: &Pagination) -> ConnectionResult<RemoteResponse>{
+    let method = request_init.method.as_method();
+    request_init.uri = match pagination.first_uri(&request_init.uri){
+        Some(uri) => uri,
+        None => return Err(ConnectionError::InvalidURI)
+    };
+    let first = send_with_policy(&request_init, method.clone()).await?;
+    let mut merged : serde_json::Value = match serde_json::from_slice(&first.body){
+        Ok(v) => v,
+        Err(_) => return Err(ConnectionError::InvalidJSON)
+    };
+    let mut page = merged.clone();
+    let mut headers = first.headers.clone();
+    let mut page_items = match pagination.items_mut(&mut merged){
+        Some(items) => items.len(),
+        None => return Err(ConnectionError::InvalidJSON)
+    };
+    let mut pages = 1;
+    while pages < pagination.max_pages{
+        let next = match pagination.next_uri(&request_init.uri,&page,&headers,page_items){
+            Some(uri) => uri,
+            None => break
+        };
+        request_init.uri = next;
+        let response = send_with_policy(&request_init, method.clone()).await?;
+        page = match serde_json::from_slice(&response.body){
+            Ok(v) => v,
+            Err(_) => return Err(ConnectionError::InvalidJSON)
+        };
+        headers = response.headers;
+        let items = match pagination.items_mut(&mut page){
+            Some(items) => std::mem::take(items),
+            None => return Err(ConnectionError::InvalidJSON)
+        };
+        page_items = items.len();
+        if let Some(all) = pagination.items_mut(&mut merged){
+            all.extend(items);
         }
+        pages += 1;
+    }
+    if pages == pagination.max_pages && pagination.next_uri(&request_init.uri,&page,&headers,page_items).is_some(){
+        println!("Pagination stopped at the page limit of {}",pagination.max_pages);
+    }
+    match serde_json::to_vec(&merged){
+        Ok(bytes) => Ok(RemoteResponse{ headers: first.headers, body: bytes.into() }),
+        Err(_) => Err(ConnectionError::InvalidJSON)
     }
-    
 }
\ No newline at end of file
@@ -97,6 +97,24 @@ impl RemoteResultType{
         }
         
     }
+    pub fn as_str(&self) -> &str{
+        match self{
+            RemoteResultType::RemoteJSON(JSONKind::RemoteItem) => "conditions",
+            RemoteResultType::RemoteJSON(JSONKind::ProductList) => "productlist",
+            RemoteResultType::RemoteJSON(JSONKind::Untyped) => "untyped",
+            RemoteResultType::RemoteJSON(JSONKind::UntypedValue) => "json",
+            RemoteResultType::RemoteJSON(JSONKind::ProxyData) => "proxydata",
+            RemoteResultType::RemoteTXT => "text",
+            RemoteResultType::RemoteBytes => "bytes"
+        }
+    }
+    pub fn content_type(&self) -> &'static str{
+        match self{
+            RemoteResultType::RemoteJSON(_) => "application/json",
+            RemoteResultType::RemoteTXT => "text/plain; charset=utf-8",
+            RemoteResultType::RemoteBytes => "application/octet-stream"
+        }
+    }
     pub fn supports_schema(&self) -> bool{
         match self{
             RemoteResultType::RemoteJSON(JSONKind::UntypedValue) => true,
@@ -168,6 +186,9 @@ pub struct RemoteData{
 }
 
 impl RemoteData{
+    pub fn new(kind: RemoteResultType, data: Vec<u8>) -> Self{
+        RemoteData{ kind, data }
+    }
     pub fn data_bytes(&self) -> &Vec<u8>{
         &self.data
     }
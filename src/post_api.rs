@@ -102,6 +102,25 @@ async fn try_save_data(req: Request<hyper::body::Incoming>) -> HyperResult{
     }
 }
 
+// Reads the body but fails with FileTooLarge as soon as the limit is exceeded, also for chunked bodies
+pub async fn read_limited_body(req: Request<hyper::body::Incoming>, limit: u64) -> Result<Vec<u8>,std::io::Error>{
+    use std::io::{Error,ErrorKind};
+    if req.body().size_hint().lower() > limit{
+        return Err(Error::from(ErrorKind::FileTooLarge))
+    }
+    let limited = http_body_util::Limited::new(req.into_body(),limit.min(usize::MAX as u64) as usize);
+    match limited.collect().await{
+        Ok(body) => Ok(body.to_bytes().to_vec()),
+        Err(e) => match e.downcast_ref::<http_body_util::LengthLimitError>(){
+            Some(_) => Err(Error::from(ErrorKind::FileTooLarge)),
+            None => {
+                eprintln!("{}",e);
+                Err(Error::from(ErrorKind::UnexpectedEof))
+            }
+        }
+    }
+}
+
 pub async fn read_post_body(req: Request<hyper::body::Incoming>) -> Result<Vec<u8>,std::io::Error>{
     use bytes::Buf;
     use std::io::Read;
@@ -17,7 +17,8 @@ pub enum ValidationError{
     OutOfRange,
     MissingRequired,
     UnexpectedProperty,
-    PatternMatchFailed
+    PatternMatchFailed,
+    Property(String,Box<ValidationError>)
 }
 
 #[allow(unused)]
@@ -68,12 +69,35 @@ impl std::fmt::Display for ValidationError {
             ValidationError::NotFloat => write!(f, "Expected float"),
             ValidationError::MissingRequired => write!(f, "Missing required property"),
             ValidationError::UnexpectedProperty => write!(f, "Unexpected property"),
-            ValidationError::PatternMatchFailed => write!(f, "Input doesn't match pattern")
+            ValidationError::PatternMatchFailed => write!(f, "Input doesn't match pattern"),
+            ValidationError::Property(_,e) => write!(f, "{} at '{}'",e.reason(),self.path())
 
         }
     }
 }
 
+impl ValidationError{
+    fn at(key: &str, e: ValidationError) -> Self{
+        ValidationError::Property(key.to_string(),Box::new(e))
+    }
+    // Dot separated path to the property that failed validation
+    pub fn path(&self) -> String{
+        match self{
+            ValidationError::Property(key,e) => match e.as_ref(){
+                ValidationError::Property(..) => format!("{}.{}",key,e.path()),
+                _ => key.clone()
+            },
+            _ => String::new()
+        }
+    }
+    pub fn reason(&self) -> &ValidationError{
+        match self{
+            ValidationError::Property(_,e) => e.reason(),
+            _ => self
+        }
+    }
+}
+
 #[derive(Debug)]
 struct NumberNode{
     min: Option<f64>,
@@ -194,10 +218,12 @@ impl ArrayNode{
                         return Err(ValidationError::OutOfRange)
                     }
                 }
-                match v.iter().all(|x| self.items.matches(x).is_ok()){
-                    true => Ok(()),
-                    false => Err(ValidationError::Invalid)
+                for (i,item) in v.iter().enumerate(){
+                    if let Err(e) = self.items.matches(item){
+                        return Err(ValidationError::at(&i.to_string(),e))
+                    }
                 }
+                Ok(())
             },
             None => Err(ValidationError::NotArray)
         }
@@ -238,24 +264,24 @@ impl ObjectNode{
     pub fn matches(&self, input: &Value) -> Result<(),ValidationError> {
         match input.as_object(){
             Some(map) => {
-                if !self.required.iter().all(|x| map.contains_key(x)){
+                if let Some(missing) = self.required.iter().find(|x| !map.contains_key(*x)){
                     println!("Missing property");
-                    return Err(ValidationError::MissingRequired)
+                    return Err(ValidationError::at(missing,ValidationError::MissingRequired))
                 }
                 for (key,val) in map.iter(){
                     match (self.allow_additional, self.properties.get(key)){
                         (true, None) => continue,
                         (true, Some(v)) => if let Err(e) = v.matches(val){
                             eprintln!("{}, ({})",e,key);
-                            return Err(ValidationError::Invalid)
+                            return Err(ValidationError::at(key,e))
                         },
                         (false, None) => {
                             eprintln!("Found unexpected property {}",key);
-                            return Err(ValidationError::UnexpectedProperty)
+                            return Err(ValidationError::at(key,ValidationError::UnexpectedProperty))
                         },
                         (false, Some(v)) => if let Err(e) = v.matches(val){
                             eprintln!("{}, ({})",e,key);
-                            return Err(ValidationError::Invalid)
+                            return Err(ValidationError::at(key,e))
                         }
                     }
                 }
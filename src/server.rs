@@ -77,7 +77,9 @@ pub async fn update_task(conf: &Settings) -> TaskResult{
         Some(res) => res,
         None => return Err(TaskError::Failure)
     };
-    let creds = match resource.request_credentials("2.718281828459045"){
+    // Older releases decoded these with "2.718281828459045", XXTEA only uses the first 16 bytes of the key
+    // so legacy values still decode with OBFUSCATION_KEY
+    let creds = match resource.request_credentials(){
         Some(res) => match res{
             Ok(dec) => Some(dec),
             Err(_) => return Err(TaskError::InvalidResource)
@@ -90,17 +92,30 @@ pub async fn update_task(conf: &Settings) -> TaskResult{
         user_agent: &conf.user_agent,
         method: &ResourceMethod::Get,
         body: None,
-        request_headers: &resource.request_headers
+        content_type: None,
+        request_headers: &resource.request_headers,
+        forwarded_headers: vec![],
+        timeouts: &resource.timeouts,
+        retry: &resource.retry,
+        client: conf.upstream_client(resource),
+        limiter: resource.limiter.as_deref(),
+        pagination: resource.pagination.as_ref(),
+        oauth: resource.oauth.as_ref(),
+        cookie_jar: resource.cookie_jar.as_deref(),
+        session: match conf.session_login(resource){
+            Ok(session) => session,
+            Err(_) => return Err(TaskError::InvalidResource)
+        }
     };
     let data_kind = match &resource.model{
         RemoteResultType::RemoteJSON(kind) => kind.clone(),
         _ => return Err(TaskError::Failure)
     };
-    let result = match request_optionally_validated_json(request_init, data_kind, conf.get_schema(&resource.schema)).await{
-        Ok(r) => match &resource.target{
+    let result = match request_optionally_validated_json(request_init, data_kind, conf.get_schema(&resource.schema), resource.transform.as_ref()).await{
+        Ok(upstream) => match &resource.target{
             Some(res) => {
                 match conf.run_mode{
-                    RuntimeMode::Normal => match res.write_file(&r).await{
+                    RuntimeMode::Normal => match res.write_file(&upstream.data).await{
                         Ok(_) => println!("file saved!"),
                         Err(e) => {
                             eprintln!("{:?}",e);
@@ -109,9 +124,9 @@ pub async fn update_task(conf: &Settings) -> TaskResult{
                     },
                     _ => ()
                 }
-                r
+                upstream.data
             },
-            None => r
+            None => upstream.data
         },
         Err(e) => {
           eprintln!("{:?}",e);
@@ -127,6 +142,9 @@ pub async fn start_server(conf: &Settings) -> TaskResult {
         None => println!("Server hosting content at './'"),
         Some(_) => println!("Server hosting content at './{}/'",conf.server_root)
     };
+    if let Some(count) = conf.warm_caches(){
+        println!("Restored {} cached resources from disk",count);
+    }
     // This address is localhost
     let addr: SocketAddr = ([127, 0, 0, 1], conf.port).into();
     
@@ -231,6 +249,16 @@ pub async fn start_server(conf: &Settings) -> TaskResult {
                         return ServiceResponse::ServiceUnavailable.resolve(req)
                     }
                     let response = match (req.method(), req.uri().path()) {
+                        (&Method::GET,"/api/health") => {
+                            let conf = match crate::SERVER_CONF.get(){
+                                Some(c) => c,
+                                None => return ServiceResponse::BadRequest.resolve(req)
+                            };
+                            if !conf.has_required_headers(req.headers()){
+                                return ServiceResponse::BadRequest.resolve(req)
+                            }
+                            ServiceResponse::Health
+                        },
                         (&Method::GET,path) => match path.strip_prefix("/api/"){
                             Some(command) => {
                                 let conf = match crate::SERVER_CONF.get(){
@@ -238,7 +266,7 @@ pub async fn start_server(conf: &Settings) -> TaskResult {
                                     None => return ServiceResponse::BadRequest.resolve(req)
                                 };
                                 match conf.get_api(command){
-                                    Some(api) => ServiceResponse::CommandResponse(ServerCommand::GetAPIRequest(api)),
+                                    Some((api,params)) => ServiceResponse::CommandResponse(ServerCommand::GetAPIRequest(api,params)),
                                     None => ServiceResponse::NotFound
                                 }
                           },
@@ -251,12 +279,29 @@ pub async fn start_server(conf: &Settings) -> TaskResult {
                                     None => return ServiceResponse::BadRequest.resolve(req)
                                 };
                                 match conf.post_api(command){
-                                    Some(api) => ServiceResponse::CommandResponse(ServerCommand::PostAPIRequest(api)),
+                                    Some((api,params)) => ServiceResponse::CommandResponse(ServerCommand::PostAPIRequest(api,params)),
                                     None => ServiceResponse::PostAPIResponse
                                 }
                           },
                           None => ServiceResponse::NotFoundEmpty
                         },
+                        (&Method::PUT,path) | (&Method::PATCH,path) | (&Method::DELETE,path) => match path.strip_prefix("/api/"){
+                            Some(command) => {
+                                let conf = match crate::SERVER_CONF.get(){
+                                    Some(c) => c,
+                                    None => return ServiceResponse::BadRequest.resolve(req)
+                                };
+                                match conf.method_api(req.method(),command){
+                                    Some((api,params)) => ServiceResponse::CommandResponse(match *req.method(){
+                                        Method::PUT => ServerCommand::PutAPIRequest(api,params),
+                                        Method::PATCH => ServerCommand::PatchAPIRequest(api,params),
+                                        _ => ServerCommand::DeleteAPIRequest(api,params)
+                                    }),
+                                    None => ServiceResponse::NotFound
+                                }
+                            },
+                            None => ServiceResponse::BadMethod
+                        },
                         (&Method::HEAD,"/api/shutdown") => {
                             let conf = match crate::SERVER_CONF.get(){
                                 Some(c) => c,
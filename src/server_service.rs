@@ -6,13 +6,15 @@ use hyper::{Method, Request, Response, StatusCode, HeaderMap};
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 
-use crate::settings::resource::{RemoteResource,ResourceMethod};
-use crate::settings::commandapi::ServerAPI;
+use crate::settings::resource::{RemoteResource,ResourceMethod,ResourceFallback};
+use crate::settings::commandapi::{ServerAPI,CompositeCommand};
 use crate::SERVER_CONF;
-use crate::httpsconnector::{request_optionally_validated_json,ConnectionError};
-use crate::post_api::read_post_body;
-use crate::models::{RemoteResultType,RemoteData};
+use crate::httpsconnector::{request_optionally_validated_json,ConnectionError,RequestOptions,UpstreamData};
+use crate::post_api::{read_post_body,read_limited_body};
+use crate::schemers::validator::Validator;
+use crate::models::{RemoteResultType,RemoteData,JSONKind};
 use crate::service_response::ServiceResponse;
+use crate::cache::CacheEntry;
 use crate::content_type::{NegotiationError,ContentType};
 
 
@@ -24,26 +26,88 @@ static INDEX: &str = "/index.html";
 
 
 pub enum ServerCommand<'a>{
-    GetAPIRequest(&'a ServerAPI),
-    PostAPIRequest(&'a ServerAPI)
+    GetAPIRequest(&'a ServerAPI,Vec<String>),
+    PostAPIRequest(&'a ServerAPI,Vec<String>),
+    PutAPIRequest(&'a ServerAPI,Vec<String>),
+    PatchAPIRequest(&'a ServerAPI,Vec<String>),
+    DeleteAPIRequest(&'a ServerAPI,Vec<String>)
 }
 
-fn command_task_resolved(json_data: RemoteData) -> HyperResponse {
+pub(crate) fn command_task_resolved(upstream: UpstreamData) -> HyperResponse {
+    if upstream.data.data_bytes().is_empty(){
+        // Allow-listed headers such as Location matter for empty responses too
+        let mut builder = Response::builder().status(StatusCode::NO_CONTENT);
+        for (name,value) in upstream.headers.iter(){
+            builder = builder.header(name,value);
+        }
+        return builder
+            .body(Full::new(Bytes::new()).map_err(|e| match e {}).boxed())
+            .unwrap()
+    }
+    let mut builder = Response::builder().status(StatusCode::OK);
+    // Allow-listed upstream Content-Type takes precedence over the one derived from the model
+    if !upstream.headers.contains_key(hyper::header::CONTENT_TYPE){
+        builder = builder.header(hyper::header::CONTENT_TYPE,upstream.data.kind.content_type());
+    }
+    for (name,value) in upstream.headers.iter(){
+        builder = builder.header(name,value);
+    }
+    builder
+        .body(Full::new(upstream.data.into_bytes().into()).map_err(|e| match e {}).boxed())
+        .unwrap()
+}
+
+fn command_task_stale(json_data: RemoteData, age: std::time::Duration) -> HyperResponse {
     Response::builder()
         .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE,json_data.kind.content_type())
+        .header("Warning","110 - \"Response is Stale\"")
+        .header("Age",age.as_secs())
+        .header("X-Stale-Data","true")
         .body(Full::new(json_data.into_bytes().into()).map_err(|e| match e {}).boxed())
         .unwrap()
 }
 
+// Looks for the most recent successful response from memory, file_target and the persisted cache, in that order
+async fn last_good_data(resource: &RemoteResource, conf: &crate::Settings<'_>, path_params: &[String]) -> Option<(RemoteData,std::time::Duration)>{
+    if let Some(stale) = resource.get_stale(path_params){
+        return Some(stale)
+    }
+    // file_target doesn't record which path it was written from
+    if let Some(target) = resource.target.as_ref().filter(|_| !resource.uri.is_templated()){
+        match target.read_file().await{
+            Ok((bytes,age)) => return Some((RemoteData::new(resource.model.clone(),bytes),age)),
+            Err(e) => eprintln!("{e}")
+        }
+    }
+    match &conf.cache_store{
+        Some(disk_cache) => match disk_cache.load(&resource.name){
+            Ok(entry) if !resource.entry_matches(&entry.uri,path_params) => None,
+            Ok(entry) => {
+                let age = entry.age();
+                Some((entry.data,age))
+            },
+            Err(e) => {
+                eprintln!("{e}");
+                None
+            }
+        },
+        None => None
+    }
+}
+
 pub async fn run_command(command: &ServerCommand<'_>, request: Request<hyper::body::Incoming>) -> HyperResult {
     let conf = match SERVER_CONF.get(){
         Some(c) => c,
         None => return ServiceResponse::not_found()
     };
     
-    let server_api = match command{
-        ServerCommand::GetAPIRequest(comm) => comm,
-        ServerCommand::PostAPIRequest(comm) => comm
+    let (server_api,path_params) = match command{
+        ServerCommand::GetAPIRequest(comm,params) => (comm,params),
+        ServerCommand::PostAPIRequest(comm,params) => (comm,params),
+        ServerCommand::PutAPIRequest(comm,params) => (comm,params),
+        ServerCommand::PatchAPIRequest(comm,params) => (comm,params),
+        ServerCommand::DeleteAPIRequest(comm,params) => (comm,params)
     };
     if !server_api.has_required_headers(request.headers()){
         return ServiceResponse::bad_request()
@@ -51,48 +115,172 @@ pub async fn run_command(command: &ServerCommand<'_>, request: Request<hyper::bo
     if server_api.is_data(){
         return server_api.resolve_as_data()
     }
+    if let Some(composite) = server_api.as_composite(){
+        return composite_task(composite,conf,request).await
+    }
     // At this point, the command has to be a RequestCommand, and thus there MUST exist a corresponding resource
     let api_command = server_api.as_command().unwrap();
     let resource = conf.get_command_resource(api_command);
 
-    match do_command_task(resource,conf,request).await{
+    let result = do_command_task(resource,conf,path_params,request).await;
+    answer_command(resource,conf,path_params,result).await
+}
+
+// Failed requests are answered with the last good data of the resource when it falls back to it
+pub(crate) async fn answer_command(resource: &RemoteResource, conf: &crate::Settings<'_>, path_params: &[String], result: Result<UpstreamData,ConnectionError>) -> HyperResult{
+    match result{
         Ok(s) => Ok(command_task_resolved(s)),
+        Err(ConnectionError::InvalidRequest) => ServiceResponse::not_found(),
+        Err(ConnectionError::RateLimited) => ServiceResponse::too_many_requests(),
+        Err(ConnectionError::InvalidBody(path,reason)) => ServiceResponse::unprocessable_entity(serde_json::json!({
+            "error": "Request body doesn't match schema",
+            "path": path,
+            "reason": reason
+        })),
+        Err(ConnectionError::BodyTooLarge) => ServiceResponse::content_too_large(),
+        Err(_) if resource.fallback == ResourceFallback::LastGood => match last_good_data(resource,conf,path_params).await{
+            Some((data,age)) => {
+                println!("Upstream failed, serving last good data");
+                Ok(command_task_stale(data,age))
+            },
+            None => ServiceResponse::not_found()
+        },
+        Err(ConnectionError::CircuitOpen) => ServiceResponse::circuit_open(resource.breaker.as_ref().map_or(1,|b| b.retry_after())),
         Err(_) => ServiceResponse::not_found()
     }
 }
 
-async fn do_command_task(resource : &RemoteResource, conf: &crate::Settings<'_>,request : Request<hyper::body::Incoming>) -> Result<RemoteData,ConnectionError>{
-        match resource.get_cached(){
-        Some(res) => {
-            println!("Returning cached data");
-            return Ok(res.clone())
-        },
-        None => ()
+async fn do_command_task(resource : &RemoteResource, conf: &crate::Settings<'_>, path_params: &[String], request : Request<hyper::body::Incoming>) -> Result<UpstreamData,ConnectionError>{
+    let query = match request.uri().query(){
+        Some(s) => Some(s.to_owned()),
+        None => None
     };
+    let incoming_headers = request.headers().clone();
+    let body = match resource.method.has_body(){
+        false => None,
+        true => {
+            let body = match resource.max_body_size{
+                Some(limit) => read_limited_body(request,limit).await,
+                None => read_post_body(request).await
+            };
+            let body = match body{
+                Ok(b) => b,
+                Err(e) if e.kind() == std::io::ErrorKind::FileTooLarge => return Err(ConnectionError::BodyTooLarge),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ConnectionError::InvalidRequest)
+                }
+            };
+            if let Some(validator) = conf.get_schema(&resource.request_schema){
+                validate_request_body(&body,validator)?;
+            }
+            Some(body)
+        }
+    };
+    resource_task(resource,conf,path_params,&incoming_headers,query.as_deref(),body).await
+}
+
+// Members are fetched concurrently, a failed member falls back to its last good data when configured
+async fn composite_task(composite: &CompositeCommand, conf: &crate::Settings<'_>, request: Request<hyper::body::Incoming>) -> HyperResult{
+    let query = request.uri().query();
+    let headers = request.headers();
+    let tasks = composite.members().iter().map(|(name,command)| async move {
+        let resource = conf.get_command_resource(command);
+        let result = match resource_task(resource,conf,&[],headers,query,None).await{
+            Ok(upstream) => Ok(upstream.data.into_bytes()),
+            Err(e) if resource.fallback == ResourceFallback::LastGood => match last_good_data(resource,conf,&[]).await{
+                Some((data,_)) => Ok(data.into_bytes()),
+                None => Err(e)
+            },
+            Err(e) => Err(e)
+        };
+        (name.as_str(),result)
+    });
+    let results = futures_util::future::join_all(tasks).await;
+    match composite.merge(results){
+        Ok((merged,failed)) => {
+            let mut builder = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type","application/json");
+            if !failed.is_empty(){
+                builder = builder.header("X-Failed-Resources",failed.join(","));
+            }
+            Ok(builder
+                .body(Full::new(merged.to_string().into()).map_err(|e| match e {}).boxed())
+                .unwrap())
+        },
+        Err(failed) => ServiceResponse::bad_gateway(serde_json::json!({
+            "error": "Composite request failed",
+            "failed": failed
+        }))
+    }
+}
+
+// Answers from cache when possible, otherwise calls the upstream of the resource
+pub(crate) async fn resource_task(resource : &RemoteResource, conf: &crate::Settings<'_>, path_params: &[String], incoming_headers: &HeaderMap, query: Option<&str>, body: Option<Vec<u8>>) -> Result<UpstreamData,ConnectionError>{
     let data_kind = match &resource.model{
         RemoteResultType::RemoteJSON(kind) => kind.clone(),
         _ => return Err(ConnectionError::NotSupported)
     };
-    let request_init = match resource.method{
-        ResourceMethod::Get => resource.build_request(conf.user_agent.as_str(), request.uri().query(),None)?,
-        ResourceMethod::Post => {
-            let query = match request.uri().query(){
-                Some(s) => Some(s.to_owned()),
-                None => None
-            };
-            let body = read_post_body(request).await;
-            if let Err(e) = body{
-                eprintln!("{e}");
-                return Err(ConnectionError::InvalidRequest)
-            }
-            // Should maybe check against schema or something
-            resource.build_request(conf.user_agent.as_str(), query.as_deref(),Some(body.unwrap().into()))?
+    conf.prepare_credentials(resource).await;
+    let mut request_init = resource.build_request(conf.upstream_client(resource), conf.user_agent.as_str(), Some(incoming_headers), path_params, query, body.map(|b| b.into()))?;
+    let key = request_key(&request_init);
+    if resource.method.is_cacheable() && resource.shares_responses() && let Some(res) = resource.get_cached_response(&key){
+        println!("Returning cached data");
+        return Ok(res)
+    }
+    request_init.session = conf.session_login(resource)?;
+    match resource.method{
+        // Identical concurrent GET requests share a single upstream call
+        ResourceMethod::Get => resource.inflight.run(&key,fetch_upstream(resource,conf,request_init,data_kind)).await,
+        _ => fetch_upstream(resource,conf,request_init,data_kind).await
+    }
+}
+
+// Cached and in-flight responses are keyed by the composed URI and the forwarded client headers,
+// without forwarded headers the key is the URI itself
+fn request_key(request_init: &RequestOptions<'_>) -> String{
+    let mut key = request_init.uri.to_string();
+    for (name,value) in request_init.forwarded_headers.iter(){
+        key.push_str(&format!("\n{}: {}",name,String::from_utf8_lossy(value.as_bytes())));
+    }
+    key
+}
+
+pub fn validate_request_body(body: &[u8], validator: &Validator) -> Result<(),ConnectionError>{
+    let value : serde_json::Value = match serde_json::from_slice(body){
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(ConnectionError::InvalidBody(String::new(),"Request body is not valid JSON".to_string()))
         }
     };
+    match validator.validate(&value){
+        Ok(()) => Ok(()),
+        Err(e) => Err(ConnectionError::InvalidBody(e.path(),e.reason().to_string()))
+    }
+}
 
-    match request_optionally_validated_json(request_init, data_kind, conf.get_schema(&resource.schema)).await{
-        Ok(r) => {
-            
+async fn fetch_upstream(resource : &RemoteResource, conf: &crate::Settings<'_>, request_init: RequestOptions<'_>, data_kind: JSONKind) -> Result<UpstreamData,ConnectionError>{
+    let request_uri = request_init.uri.clone();
+    if let Some(breaker) = &resource.breaker{
+        if !breaker.try_acquire(){
+            return Err(ConnectionError::CircuitOpen)
+        }
+    }
+    let result = request_optionally_validated_json(request_init, data_kind, conf.get_schema(&resource.schema), resource.transform.as_ref()).await;
+    if let Some(breaker) = &resource.breaker{
+        match &result{
+            Ok(_) => breaker.record_success(),
+            Err(e) if e.is_upstream_failure() => breaker.record_failure(),
+            // Rate limiting, auth and invalid data say nothing about the health of the upstream
+            Err(_) => breaker.release()
+        }
+    }
+    match result{
+        Ok(upstream) => {
+            let r = upstream.data;
+            let headers = resource.select_response_headers(&upstream.headers);
             match &resource.target{
                 Some(res) => {
                     match res.write_file(&r).await{
@@ -103,12 +291,19 @@ async fn do_command_task(resource : &RemoteResource, conf: &crate::Settings<'_>,
                 },
                 None => ()
             }
-            if resource.no_cache {
-                return Ok(r)
+            if resource.no_cache || !resource.method.is_cacheable() || !resource.shares_responses(){
+                return Ok(UpstreamData{ data: r, headers })
             }
             println!("Inserting to cache...");
-            match resource.cache_result(r){
-                Ok(r) => Ok(r),
+            // Shared responses have no forwarded headers, so the URI is the whole cache key
+            let entry = CacheEntry::new(r,&request_uri,&upstream.headers).with_headers(headers.clone());
+            if let Some(disk_cache) = &conf.cache_store{
+                if let Err(e) = disk_cache.store(&resource.name,&entry).await{
+                    eprintln!("{e}");
+                }
+            }
+            match resource.cache_result(entry){
+                Ok(data) => Ok(UpstreamData{ data, headers }),
                 Err(_) => Err(ConnectionError::InternalError)
             }
         },
@@ -119,6 +314,26 @@ async fn do_command_task(resource : &RemoteResource, conf: &crate::Settings<'_>,
     }
 }
 
+pub fn health_report() -> HyperResult {
+    let conf = match SERVER_CONF.get(){
+        Some(c) => c,
+        None => return ServiceResponse::service_unavailable()
+    };
+    let body = match serde_json::to_vec_pretty(&conf.health_report()){
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{e}");
+            return ServiceResponse::internal_server_error()
+        }
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type","application/json")
+        .header("Cache-Control","no-store")
+        .body(Full::new(body.into()).map_err(|e| match e {}).boxed())
+        .unwrap())
+}
+
 pub async fn file_serve( req: Request<hyper::body::Incoming>) -> HyperResult {
     
     match (req.method(), req.uri().path()) {
@@ -1,7 +1,7 @@
 #![deny(warnings)]
 use std::future::{IntoFuture,ready,Ready};
 use hyper::{Request,StatusCode,Response};
-use crate::server_service::{HyperResult,ServerCommand,run_command,file_serve};
+use crate::server_service::{HyperResult,ServerCommand,run_command,file_serve,health_report};
 use http_body_util::{BodyExt, Full, Empty};
 use crate::post_api::handle_post_api;
 
@@ -19,7 +19,8 @@ pub enum ServiceResponse<'a>{
     Accepted,
     FileService,
     BadMethod,
-    BadRequest
+    BadRequest,
+    Health
 }
 
 impl IntoFuture for ServiceResponse<'_>{
@@ -35,7 +36,8 @@ impl IntoFuture for ServiceResponse<'_>{
             ServiceResponse::Accepted           => ready(ServiceResponse::accepted()),
             ServiceResponse::FileService     => panic!("FileService should not get called"),
             ServiceResponse::BadMethod          => ready(ServiceResponse::bad_method()),
-            ServiceResponse::BadRequest         => ready(ServiceResponse::bad_request())
+            ServiceResponse::BadRequest         => ready(ServiceResponse::bad_request()),
+            ServiceResponse::Health             => ready(health_report())
         }
     }
 }
@@ -91,6 +93,38 @@ impl ServiceResponse<'_>{
         .unwrap())
     }
 
+    pub fn too_many_requests() -> HyperResult {
+        Ok(Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Retry-After","1")
+        .body(Full::new("Too Many Requests".into()).map_err(|e| match e {}).boxed())
+        .unwrap())
+    }
+
+    pub fn unprocessable_entity(details: serde_json::Value) -> HyperResult {
+        Ok(Response::builder()
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .header("Content-Type","application/json")
+        .body(Full::new(details.to_string().into()).map_err(|e| match e {}).boxed())
+        .unwrap())
+    }
+
+    pub fn bad_gateway(details: serde_json::Value) -> HyperResult {
+        Ok(Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("Content-Type","application/json")
+        .body(Full::new(details.to_string().into()).map_err(|e| match e {}).boxed())
+        .unwrap())
+    }
+
+    pub fn circuit_open(retry_after: u64) -> HyperResult {
+        Ok(Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Retry-After",retry_after.to_string())
+        .body(Full::new(SERVICE_UNAVAILABLE.into()).map_err(|e| match e {}).boxed())
+        .unwrap())
+    }
+
     pub fn content_too_large() -> HyperResult {
         Ok(Response::builder()
         .status(StatusCode::PAYLOAD_TOO_LARGE)
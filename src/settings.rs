@@ -3,22 +3,43 @@
 use config::Config;
 use std::collections::{HashSet,HashMap};
 use std::path::Path;
+use std::sync::Arc;
 
 use crate::Commands;
 use crate::schemers::{schemaloader::{build_test,SchemaTree},validator::Validator};
 use crate::content_type::{ContentType,HeaderMap,GetHeaderValueString};
+use crate::cache::DiskCache;
+use crate::support::secrets::SecretKey;
+use crate::httpsconnector::{ClientPool,PoolOptions,HttpClient,SessionLogin,ConnectionError};
 
 pub(crate) mod header;
 mod pathprovider;
 pub mod resource;
 mod qualifieduri;
 mod credentials;
+pub(crate) mod oauth;
+pub(crate) mod login;
+pub(crate) mod cookiejar;
+pub(crate) mod tls;
+pub(crate) mod proxy;
+pub(crate) mod resolve;
+pub(crate) mod retry;
+pub(crate) mod breaker;
+pub(crate) mod limits;
+pub(crate) mod bodytemplate;
+pub(crate) mod transform;
+pub(crate) mod pagination;
 pub(crate) mod commandapi;
 
 pub(crate) use header::{HeaderValue,HeaderSet,ParseMode};
 use commandapi::{ServerAPI,CommandAPI,RequestCommand};
 use pathprovider::PathProvider;
 use resource::{ResourceStore,RemoteResource,TryParseTypedValue};
+use limits::UpstreamLimiter;
+use cookiejar::CookieJar;
+use tls::TlsOptions;
+use proxy::ProxySettings;
+use resolve::ResolveOverrides;
 
 pub type ServerConfigResult<T> = Result<T, ServerConfigError>;
 impl std::fmt::Display for ServerConfigError {
@@ -124,6 +145,48 @@ impl WebviewOptions{
     }
 }
 
+fn parse_pool_options(config: &Config) -> PoolOptions{
+    let defaults = PoolOptions::default();
+    match config.get_table("upstream_pool"){
+        Ok(table) => PoolOptions{
+            max_idle_per_host: match table.try_parse_u64("max_idle_per_host"){
+                Ok(n) => n as usize,
+                Err(_) => defaults.max_idle_per_host
+            },
+            idle_timeout: match table.try_parse_u64("idle_timeout"){
+                Ok(0) => None,
+                Ok(secs) => Some(std::time::Duration::from_secs(secs)),
+                Err(_) => defaults.idle_timeout
+            },
+            proxy: parse_upstream_proxy(config),
+            resolve: parse_resolve(config)
+        },
+        Err(_) => PoolOptions{ proxy: parse_upstream_proxy(config), resolve: parse_resolve(config), ..defaults }
+    }
+}
+
+fn parse_resolve(config: &Config) -> Arc<ResolveOverrides>{
+    match config.get_table("resolve"){
+        Ok(table) => Arc::new(ResolveOverrides::try_parse_hosts(&table)),
+        Err(_) => Arc::new(ResolveOverrides::default())
+    }
+}
+
+// Without an [upstream_proxy] table the usual proxy environment variables are followed
+fn parse_upstream_proxy(config: &Config) -> Option<Arc<ProxySettings>>{
+    let proxy = match config.get::<config::Value>("upstream_proxy"){
+        Ok(value) => match ProxySettings::try_parse(&value){
+            Ok(proxy) => proxy,
+            Err(e) => {
+                eprintln!("Upstream proxy settings are ignored: {e}");
+                None
+            }
+        },
+        Err(_) => ProxySettings::from_env()
+    };
+    proxy.map(Arc::new)
+}
+
 pub(crate) fn parse_config_string_table(config : &HashMap<String, config::Value>, table_name: &str) -> Option<HashMap<String,String>>{
     match config.get(table_name){
         Some(t) => match t.clone().into_table(){
@@ -168,7 +231,33 @@ pub struct Settings<'a>{
     schema_tree: Option<SchemaTree>,
     pub allow_origins: HashSet<String>,
     api_required_headers: Option<HashMap<String,String>>,
-    commands: Option<CommandAPI>
+    commands: Option<CommandAPI>,
+    pub cache_store: Option<DiskCache>,
+    pub secret_key: Arc<SecretKey>,
+    upstream_clients: ClientPool
+}
+
+// Environment variable that holds the secret key when [secrets] doesn't say otherwise
+const DEFAULT_KEY_ENV : &str = "RUDDLE_SECRET_KEY";
+
+// Without a key only legacy credentials can be decoded, a configured key that can't be loaded stops the startup
+fn parse_secret_key(config: &Config) -> SecretKey{
+    let table = config.get_table("secrets").unwrap_or_default();
+    let key_env = table.try_parse_string("key_env");
+    let key_file = table.try_parse_string("key_file");
+    let key = match (key_env,key_file){
+        (Ok(_),Ok(_)) => panic!("[secrets] key_env and key_file can't both be set"),
+        (Ok(name),_) => SecretKey::from_env(&name),
+        (_,Ok(path)) => SecretKey::from_file(Path::new(&path)),
+        (_,_) => match std::env::var_os(DEFAULT_KEY_ENV){
+            Some(_) => SecretKey::from_env(DEFAULT_KEY_ENV),
+            None => return SecretKey::legacy_only()
+        }
+    };
+    match key{
+        Ok(k) => k,
+        Err(e) => panic!("{e}")
+    }
 }
 
 
@@ -223,6 +312,44 @@ impl Settings<'_>{
         let rr = self.remote_resources.as_ref().unwrap();
         rr.get_command_resource(request_command)
     }
+    pub fn upstream_client(&self, resource: &RemoteResource) -> &HttpClient{
+        self.upstream_clients.client_for(resource.client)
+    }
+    // Covers the credentials of the login resource too, session_login builds its request
+    pub async fn prepare_credentials(&self, resource: &RemoteResource){
+        resource.prepare_credentials().await;
+        if let Some(login) = resource.session_auth.as_ref().and_then(|auth| self.get_resource(&auth.login)){
+            login.prepare_credentials().await
+        }
+    }
+    // The login request of a resource that declares auth_from, sent only when there's no valid session
+    pub fn session_login<'a>(&'a self, resource: &'a RemoteResource) -> Result<Option<SessionLogin<'a>>,ConnectionError>{
+        let auth = match &resource.session_auth{
+            Some(auth) => auth,
+            None => return Ok(None)
+        };
+        let login = match self.get_resource(&auth.login){
+            Some(login) => login,
+            None => return Err(ConnectionError::InvalidRequest)
+        };
+        // Templated login bodies are rendered from their constants
+        let body = login.body_template.as_ref().map(|_| bytes::Bytes::new());
+        let request = login.build_request(self.upstream_client(login), self.user_agent.as_str(), None, &[], None, body)?;
+        Ok(Some(SessionLogin{ auth, login: Box::new(request) }))
+    }
+    pub fn health_report(&self) -> serde_json::Value{
+        let resources = match &self.remote_resources{
+            Some(rr) => rr.health(),
+            None => serde_json::Value::Object(serde_json::Map::new())
+        };
+        serde_json::json!({ "status": "ok", "resources": resources })
+    }
+    pub fn get_resource(&self, name: &str) -> Option<&RemoteResource>{
+        match &self.remote_resources{
+            Some(rr) => rr.inner().get(name),
+            None => None
+        }
+    }
     pub fn maybe_panics_get_command_resource(&self, request_command: &RequestCommand) -> Option<&RemoteResource>{
         let rr = match &self.remote_resources{
             Some(rr) => rr,
@@ -230,19 +357,45 @@ impl Settings<'_>{
         };
         Some(rr.get_command_resource(request_command))
     }
-    pub fn get_api(&self, resource_name: &str) -> Option<&ServerAPI>{
-        let commands = match &self.commands{
-            Some(comms) => comms,
-            None => return None
-        };
-        commands.get_api(resource_name)
+    pub fn warm_caches(&self) -> Option<usize>{
+        match (&self.remote_resources, &self.cache_store){
+            (Some(rr),Some(disk_cache)) => Some(rr.warm_caches(disk_cache)),
+            (_,_) => None
+        }
     }
-    pub fn post_api(&self, resource_name: &str) -> Option<&ServerAPI>{
+    // Trailing path segments after the api name are passed as path parameters to templated resources
+    fn find_api<'a>(&'a self, path: &str, lookup: impl Fn(&'a CommandAPI,&str) -> Option<&'a ServerAPI>) -> Option<(&'a ServerAPI,Vec<String>)>{
         let commands = match &self.commands{
             Some(comms) => comms,
             None => return None
         };
-        commands.post_api(resource_name)
+        let param_count = |api: &ServerAPI| match api.as_command(){
+            Some(command) => self.get_command_resource(command).uri.param_count(),
+            None => 0
+        };
+        if let Some(api) = lookup(commands,path).filter(|api| param_count(api) == 0){
+            return Some((api,vec![]))
+        }
+        let mut split = path.len();
+        while let Some(i) = path[..split].rfind('/'){
+            split = i;
+            if let Some(api) = lookup(commands,&path[..i]){
+                let params : Vec<String> = path[i + 1..].split('/').map(|s| s.to_string()).collect();
+                if params.len() == param_count(api){
+                    return Some((api,params))
+                }
+            }
+        }
+        None
+    }
+    pub fn get_api(&self, path: &str) -> Option<(&ServerAPI,Vec<String>)>{
+        self.find_api(path,|commands,name| commands.get_api(name))
+    }
+    pub fn post_api(&self, path: &str) -> Option<(&ServerAPI,Vec<String>)>{
+        self.find_api(path,|commands,name| commands.post_api(name))
+    }
+    pub fn method_api(&self, method: &hyper::Method, path: &str) -> Option<(&ServerAPI,Vec<String>)>{
+        self.find_api(path,|commands,name| commands.method_api(method,name))
     }
     pub fn from_config(config: Config, cli: crate::Cli) -> Settings<'static>{
         let port_number = match cli.port{
@@ -344,10 +497,23 @@ impl Settings<'_>{
                 None
             }
         };
-        let remote_store = match config.get_table("remote_resources"){
+        let host_limits = match config.get_table("upstream_limits"){
+            Ok(table) => UpstreamLimiter::try_parse_hosts(&table),
+            Err(_) => HashMap::new()
+        };
+        let host_tls = match config.get_table("upstream_tls"){
+            Ok(table) => TlsOptions::try_parse_hosts(&table),
+            Err(_) => HashMap::new()
+        };
+        let cookie_jars = match config.get_table("sessions"){
+            Ok(table) => CookieJar::try_parse_sessions(&table),
+            Err(_) => HashMap::new()
+        };
+        let secret_key = Arc::new(parse_secret_key(&config));
+        let mut remote_store = match config.get_table("remote_resources"){
             Ok(s) => match s.is_empty(){
                 true => None,
-                false => match ResourceStore::try_parse(&s,&schema_source, port_number){
+                false => match ResourceStore::try_parse(&s,&schema_source, port_number, &host_limits, &host_tls, &cookie_jars, &secret_key){
                     Ok(store) => Some(store),
                     Err(_) => None
                 },
@@ -387,11 +553,19 @@ impl Settings<'_>{
             },
             Err(_) => HashMap::new()
         };
+        let mut upstream_clients = ClientPool::new(parse_pool_options(&config));
+        if let Some(store) = remote_store.as_mut(){
+            store.assign_clients(&mut upstream_clients);
+        }
         let ref_map = match &remote_store{
             Some(store) => store.inner(),
             None => &HashMap::new()
         };
         let commands = CommandAPI::try_parse(&config,&ref_map,&api_requirements);
+        let cache_store = match config.get::<String>("cache_dir"){
+            Ok(dir) => Some(DiskCache::new(&dir)),
+            Err(_) => None
+        };
         let root = config.get::<String>("server_root").unwrap_or("server_root".to_string());
         if root.starts_with("api/") || root.starts_with("./api/") || root == "api" || root == "./api"{
             panic!("Server root directory must not be named 'api'");
@@ -410,7 +584,10 @@ impl Settings<'_>{
             commands,
             api_required_headers: api_requirements,
             run_mode,
-            subcommand: cli.command
+            subcommand: cli.command,
+            cache_store,
+            secret_key,
+            upstream_clients
         }
     }
     pub fn from_file(filename: &Path,cli: crate::Cli) -> ServerConfigResult<Settings<'static>>{
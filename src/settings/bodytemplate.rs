@@ -0,0 +1,173 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use serde_json::{Value,Map};
+use percent_encoding::{NON_ALPHANUMERIC,utf8_percent_encode};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+use crate::httpsconnector::ConnectionError;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum BodyFormat{
+    Json,
+    Form
+}
+
+impl BodyFormat{
+    pub fn content_type(&self) -> &'static str{
+        match self{
+            BodyFormat::Json => "application/json",
+            BodyFormat::Form => "application/x-www-form-urlencoded"
+        }
+    }
+}
+
+// String values starting with '$' refer to request data, "$$" escapes a literal '$'
+#[derive(Debug)]
+enum TemplateValue{
+    Constant(Value),
+    Body(Vec<String>),
+    Query(String),
+    Path(String),
+    Object(Vec<(String,TemplateValue)>),
+    Array(Vec<TemplateValue>)
+}
+
+impl TemplateValue{
+    fn try_parse(input: Value) -> Result<Self,ServerConfigError>{
+        match input{
+            Value::String(s) => match s.strip_prefix('$'){
+                Some(rest) if rest.starts_with('$') => Ok(TemplateValue::Constant(Value::String(rest.to_string()))),
+                Some("body") => Ok(TemplateValue::Body(vec![])),
+                Some(rest) => match rest.split_once('.'){
+                    Some(("body",path)) => Ok(TemplateValue::Body(path.split('.').map(|p| p.to_string()).collect())),
+                    Some(("query",name)) => Ok(TemplateValue::Query(name.to_string())),
+                    Some(("path",name)) => Ok(TemplateValue::Path(name.to_string())),
+                    _ => {
+                        eprintln!("Unknown body template reference: {s}");
+                        Err(ServerConfigError::InvalidValue)
+                    }
+                },
+                None => Ok(TemplateValue::Constant(Value::String(s)))
+            },
+            Value::Object(map) => {
+                let mut fields = vec![];
+                for (key,val) in map.into_iter(){
+                    fields.push((key,TemplateValue::try_parse(val)?));
+                }
+                Ok(TemplateValue::Object(fields))
+            },
+            Value::Array(list) => Ok(TemplateValue::Array(list.into_iter().map(TemplateValue::try_parse).collect::<Result<_,_>>()?)),
+            other => Ok(TemplateValue::Constant(other))
+        }
+    }
+    fn render(&self, context: &TemplateContext) -> Result<Value,ConnectionError>{
+        match self{
+            TemplateValue::Constant(v) => Ok(v.clone()),
+            TemplateValue::Body(path) => context.body_value(path),
+            TemplateValue::Query(name) => Ok(context.query.get(name).map(|v| Value::String(v.clone())).unwrap_or(Value::Null)),
+            TemplateValue::Path(name) => Ok(context.path.get(name).map(|v| Value::String(v.clone())).unwrap_or(Value::Null)),
+            TemplateValue::Object(fields) => {
+                let mut map = Map::new();
+                for (key,val) in fields.iter(){
+                    map.insert(key.clone(),val.render(context)?);
+                }
+                Ok(Value::Object(map))
+            },
+            TemplateValue::Array(items) => Ok(Value::Array(items.iter().map(|i| i.render(context)).collect::<Result<_,_>>()?))
+        }
+    }
+}
+
+// Request data available to the template, query and path values are already decoded
+pub struct TemplateContext<'a>{
+    pub body: &'a [u8],
+    pub query: HashMap<String,String>,
+    pub path: HashMap<String,String>
+}
+
+impl TemplateContext<'_>{
+    // Non-JSON bodies can only be embedded whole, as a string
+    fn body_value(&self, path: &[String]) -> Result<Value,ConnectionError>{
+        let parsed : Value = match serde_json::from_slice(self.body){
+            Ok(v) => v,
+            Err(_) if path.is_empty() => return Ok(Value::String(String::from_utf8_lossy(self.body).into_owned())),
+            Err(_) => return Err(ConnectionError::InvalidBody(String::new(),"Request body is not valid JSON".to_string()))
+        };
+        let mut current = &parsed;
+        for key in path.iter(){
+            current = match current{
+                Value::Object(map) => map.get(key),
+                Value::Array(list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+                _ => None
+            }.unwrap_or(&Value::Null);
+        }
+        Ok(current.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct BodyTemplate{
+    pub format: BodyFormat,
+    fields: TemplateValue
+}
+
+impl BodyTemplate{
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let format = match table.try_parse_string("format"){
+            Ok(f) => match f.as_str(){
+                "json" => BodyFormat::Json,
+                "form" => BodyFormat::Form,
+                _ => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => BodyFormat::Json,
+            Err(e) => return Err(e)
+        };
+        let fields = match table.get("fields"){
+            Some(f) => match f.clone().try_deserialize::<Value>(){
+                Ok(v) => TemplateValue::try_parse(v)?,
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            },
+            None => return Err(ServerConfigError::MissingKey)
+        };
+        // Form bodies are flat key-value pairs
+        if format == BodyFormat::Form && !matches!(fields,TemplateValue::Object(_)){
+            return Err(ServerConfigError::InvalidValue)
+        }
+        Ok(BodyTemplate{ format, fields })
+    }
+    pub fn render(&self, context: &TemplateContext) -> Result<Vec<u8>,ConnectionError>{
+        let value = self.fields.render(context)?;
+        match self.format{
+            BodyFormat::Json => match serde_json::to_vec(&value){
+                Ok(bytes) => Ok(bytes),
+                Err(_) => Err(ConnectionError::InternalError)
+            },
+            BodyFormat::Form => {
+                let map = match value{
+                    Value::Object(map) => map,
+                    _ => return Err(ConnectionError::InternalError)
+                };
+                let mut pairs : Vec<String> = vec![];
+                for (key,val) in map.iter(){
+                    let text = match val{
+                        Value::Null => continue,
+                        Value::String(s) => s.clone(),
+                        other => other.to_string()
+                    };
+                    pairs.push(format!("{}={}",utf8_percent_encode(key,NON_ALPHANUMERIC),utf8_percent_encode(&text,NON_ALPHANUMERIC)));
+                }
+                Ok(pairs.join("&").into_bytes())
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+#![deny(warnings)]
+use std::sync::Mutex;
+use std::time::{Duration,Instant};
+use serde_json::{Value,json};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+#[derive(Debug,Clone)]
+enum BreakerState{
+    Closed{ failures: u32 },
+    Open{ until: Instant },
+    HalfOpen{ probes: u32 }
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker{
+    failure_threshold: u32,
+    open_duration: Duration,
+    half_open_probes: u32,
+    state: Mutex<BreakerState>
+}
+
+impl CircuitBreaker{
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let failure_threshold = match table.try_parse_u64("failure_threshold"){
+            Ok(0) => return Err(ServerConfigError::InvalidValue),
+            Ok(n) => n.min(u32::MAX as u64) as u32,
+            Err(ServerConfigError::MissingKey) => 5,
+            Err(e) => return Err(e)
+        };
+        let open_duration = match table.try_parse_u64("open_duration"){
+            Ok(secs) => Duration::from_secs(secs),
+            Err(ServerConfigError::MissingKey) => Duration::from_secs(30),
+            Err(e) => return Err(e)
+        };
+        let half_open_probes = match table.try_parse_u64("half_open_probes"){
+            Ok(0) => return Err(ServerConfigError::InvalidValue),
+            Ok(n) => n.min(u32::MAX as u64) as u32,
+            Err(ServerConfigError::MissingKey) => 1,
+            Err(e) => return Err(e)
+        };
+        Ok(CircuitBreaker{
+            failure_threshold,
+            open_duration,
+            half_open_probes,
+            state: Mutex::new(BreakerState::Closed{ failures: 0 })
+        })
+    }
+    // Returns false if the request must not be sent upstream
+    pub fn try_acquire(&self) -> bool{
+        let mut state = match self.state.lock(){
+            Ok(s) => s,
+            Err(_) => return true
+        };
+        match *state{
+            BreakerState::Closed{..} => true,
+            BreakerState::Open{ until } => match Instant::now() >= until{
+                true => {
+                    *state = BreakerState::HalfOpen{ probes: 1 };
+                    true
+                },
+                false => false
+            },
+            BreakerState::HalfOpen{ probes } => match probes < self.half_open_probes{
+                true => {
+                    *state = BreakerState::HalfOpen{ probes: probes + 1 };
+                    true
+                },
+                false => false
+            }
+        }
+    }
+    pub fn record_success(&self){
+        if let Ok(mut state) = self.state.lock(){
+            *state = BreakerState::Closed{ failures: 0 };
+        }
+    }
+    // Gives back a half-open probe when the request never reached the upstream
+    pub fn release(&self){
+        if let Ok(mut state) = self.state.lock() && let BreakerState::HalfOpen{ probes } = *state{
+            *state = BreakerState::HalfOpen{ probes: probes.saturating_sub(1) };
+        }
+    }
+    pub fn record_failure(&self){
+        if let Ok(mut state) = self.state.lock(){
+            let open = BreakerState::Open{ until: Instant::now() + self.open_duration };
+            *state = match *state{
+                BreakerState::Closed{ failures } => match failures + 1 >= self.failure_threshold{
+                    true => {
+                        eprintln!("Circuit opened after {} consecutive failures",failures + 1);
+                        open
+                    },
+                    false => BreakerState::Closed{ failures: failures + 1 }
+                },
+                _ => open
+            };
+        }
+    }
+    // How long until the circuit lets a probe through, in whole seconds so it fits Retry-After
+    pub fn retry_after(&self) -> u64{
+        match self.state.lock().map(|s| s.clone()){
+            Ok(BreakerState::Open{ until }) => until.saturating_duration_since(Instant::now()).as_secs_f64().ceil().max(1.0) as u64,
+            _ => 1
+        }
+    }
+    pub fn status(&self) -> Value{
+        let state = match self.state.lock(){
+            Ok(s) => s.clone(),
+            Err(_) => return json!({ "state": "unknown" })
+        };
+        match state{
+            BreakerState::Closed{ failures } => json!({ "state": "closed", "failures": failures }),
+            BreakerState::Open{ until } => json!({
+                "state": "open",
+                "retry_in": until.saturating_duration_since(Instant::now()).as_secs()
+            }),
+            BreakerState::HalfOpen{ probes } => json!({ "state": "half_open", "probes": probes })
+        }
+    }
+}
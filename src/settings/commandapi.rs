@@ -10,12 +10,16 @@ use hyper::{StatusCode,Response};
 #[derive(Debug)]
 enum ServerAPIType{
     Get(ServerAPI),
-    Post(ServerAPI)
+    Post(ServerAPI),
+    Put(ServerAPI),
+    Patch(ServerAPI),
+    Delete(ServerAPI)
 }
 #[derive(Debug)]
 enum APIResponseType{
     Command(RequestCommand),
-    Data(DataCommand)
+    Data(DataCommand),
+    Composite(CompositeCommand)
 }
 type ConfigMap = config::Map<String, config::Value>;
 impl ServerAPIType{
@@ -24,6 +28,9 @@ impl ServerAPIType{
             Ok(met) => match met.as_str(){
                 "get" | "GET" => Ok(ServerAPIType::Get(command_api)),
                 "post" | "POST" => Ok(ServerAPIType::Post(command_api)),
+                "put" | "PUT" => Ok(ServerAPIType::Put(command_api)),
+                "patch" | "PATCH" => Ok(ServerAPIType::Patch(command_api)),
+                "delete" | "DELETE" => Ok(ServerAPIType::Delete(command_api)),
                 _ => Err(ServerConfigError::InvalidValue)
             },
             Err(_) => Err(ServerConfigError::MissingKey)
@@ -36,7 +43,10 @@ pub type APIMap = HashMap<String,ServerAPI>;
 #[derive(Debug)]
 pub struct CommandAPI{
     post: APIMap,
-    get: APIMap
+    get: APIMap,
+    put: APIMap,
+    patch: APIMap,
+    delete: APIMap
 }
 
 #[derive(Debug)]
@@ -62,6 +72,16 @@ impl CommandAPI{
     pub fn post_api(&self, api_name: &str) -> Option<&ServerAPI>{
         self.post.get(api_name)
     }
+    pub fn method_api(&self, method: &hyper::Method, api_name: &str) -> Option<&ServerAPI>{
+        match *method{
+            hyper::Method::GET => self.get.get(api_name),
+            hyper::Method::POST => self.post.get(api_name),
+            hyper::Method::PUT => self.put.get(api_name),
+            hyper::Method::PATCH => self.patch.get(api_name),
+            hyper::Method::DELETE => self.delete.get(api_name),
+            _ => None
+        }
+    }
     pub fn try_parse(conf: &config::Config, available_remotes: &HashMap<String, RemoteResource>, global_required_headers : &Option<HashMap<String,String>>) -> Option<Self>{
         let command_apis : Option<CommandAPI> = match conf.get_table("apis"){
             Ok(table) => match table.is_empty(){
@@ -69,17 +89,31 @@ impl CommandAPI{
                 false => {
                     let mut get_map : APIMap = HashMap::new();
                     let mut post_map : APIMap = HashMap::new();
+                    let mut put_map : APIMap = HashMap::new();
+                    let mut patch_map : APIMap = HashMap::new();
+                    let mut delete_map : APIMap = HashMap::new();
                     for (key,val) in table.iter(){
                         if let Ok(server_api) = ServerAPI::try_parse(val,available_remotes,global_required_headers){
                             match server_api{
+                                // GET /api/health is answered by the server itself
+                                ServerAPIType::Get(_) if key == "health" => {
+                                    eprintln!("apis.health is ignored, GET /api/health is reserved for the health endpoint");
+                                    None
+                                },
                                 ServerAPIType::Get(api) => get_map.insert(key.to_string(),api),
-                                ServerAPIType::Post(api) => post_map.insert(key.to_string(),api)
+                                ServerAPIType::Post(api) => post_map.insert(key.to_string(),api),
+                                ServerAPIType::Put(api) => put_map.insert(key.to_string(),api),
+                                ServerAPIType::Patch(api) => patch_map.insert(key.to_string(),api),
+                                ServerAPIType::Delete(api) => delete_map.insert(key.to_string(),api)
                             };
                         };
                     }
                     Some(CommandAPI{
                         get: get_map,
-                        post: post_map
+                        post: post_map,
+                        put: put_map,
+                        patch: patch_map,
+                        delete: delete_map
                     })
                 }
             },
@@ -92,6 +126,107 @@ impl CommandAPI{
     }
 }
 
+#[derive(Debug,PartialEq)]
+pub enum PartialFailure{
+    FailAll,
+    NullEntries
+}
+
+// Fetches several resources at once and merges them into one object keyed by name
+#[derive(Debug)]
+pub struct CompositeCommand{
+    members: Vec<(String,RequestCommand)>,
+    pub on_error: PartialFailure
+}
+
+impl CompositeCommand{
+    pub fn members(&self) -> &Vec<(String,RequestCommand)>{
+        &self.members
+    }
+    fn try_from_table(table: &ConfigMap, composite: &config::Value, available_remotes: &HashMap<String, RemoteResource>) -> Result<Self,ServerConfigError>{
+        // Either a list of resource names or a table of output key and resource name
+        let pairs : Vec<(String,String)> = match composite.clone().into_table(){
+            Ok(map) => {
+                let mut pairs = vec![];
+                for (key,val) in map.into_iter(){
+                    match val.into_string(){
+                        Ok(name) => pairs.push((key,name)),
+                        Err(_) => return Err(ServerConfigError::InvalidValue)
+                    }
+                }
+                pairs.sort_by(|a,b| a.0.cmp(&b.0));
+                pairs
+            },
+            Err(_) => match composite.clone().into_array(){
+                Ok(list) => {
+                    let mut pairs = vec![];
+                    for val in list.into_iter(){
+                        match val.into_string(){
+                            Ok(name) => pairs.push((name.clone(),name)),
+                            Err(_) => return Err(ServerConfigError::InvalidValue)
+                        }
+                    }
+                    pairs
+                },
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            }
+        };
+        if pairs.is_empty(){
+            return Err(ServerConfigError::InvalidValue)
+        }
+        let mut members = vec![];
+        for (key,name) in pairs.into_iter(){
+            match available_remotes.get(&name){
+                // Members are fetched without request body or path parameters
+                Some(resource) if resource.method.has_body() || resource.uri.is_templated() => {
+                    eprintln!("Resource '{name}' can't be part of a composite api");
+                    return Err(ServerConfigError::InvalidValue)
+                },
+                Some(_) => members.push((key,RequestCommand::new(&name))),
+                None => return Err(ServerConfigError::NotAvailable)
+            }
+        }
+        let on_error = match table.try_parse_string("on_error"){
+            Ok(policy) => match policy.as_str(){
+                "fail" => PartialFailure::FailAll,
+                "null" => PartialFailure::NullEntries,
+                _ => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => PartialFailure::FailAll,
+            Err(e) => return Err(e)
+        };
+        Ok(CompositeCommand{ members, on_error })
+    }
+    // Returns the merged object and names of failed members, or only the failed names if the policy rejects partial results
+    pub fn merge<E: std::fmt::Display>(&self, results: Vec<(&str,Result<Vec<u8>,E>)>) -> Result<(serde_json::Value,Vec<String>),Vec<String>>{
+        let mut merged = serde_json::Map::new();
+        let mut failed = vec![];
+        for (name,result) in results.into_iter(){
+            let value = match result{
+                Ok(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes).ok(),
+                Err(e) => {
+                    eprintln!("{name}: {e}");
+                    None
+                }
+            };
+            match value{
+                Some(v) => merged.insert(name.to_string(),v),
+                None => {
+                    failed.push(name.to_string());
+                    merged.insert(name.to_string(),serde_json::Value::Null)
+                }
+            };
+        }
+        match !failed.is_empty() && self.on_error == PartialFailure::FailAll{
+            true => Err(failed),
+            false => Ok((serde_json::Value::Object(merged),failed))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ServerAPI{
     response_type: APIResponseType,
@@ -201,6 +336,12 @@ impl ServerAPI{
             required_headers: map
         }
     }
+    pub fn as_composite(&self) -> Option<&CompositeCommand>{
+        match &self.response_type{
+            APIResponseType::Composite(composite) => Some(composite),
+            _ => None
+        }
+    }
     pub fn as_command(&self) -> Option<&RequestCommand>{
         match &self.response_type{
             APIResponseType::Command(command) => Some(command),
@@ -218,6 +359,12 @@ impl ServerAPI{
                     },
                     false => return Err(ServerConfigError::NotAvailable)
                 },
+                Err(_) if table.contains_key("composite") => {
+                    let composite = CompositeCommand::try_from_table(&table,&table["composite"],available_remotes)?;
+                    let own_headers = parse_config_string_table(&table,"require_headers");
+                    let sapi = ServerAPI::with_headers(APIResponseType::Composite(composite), own_headers, global_required_headers);
+                    ServerAPIType::try_from_table(&table,sapi)
+                },
                 Err(_) => {
                     match table.get("response"){
                         Some(res) => match DataCommand::try_from_table(res){
@@ -0,0 +1,301 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+use serde_json::{Value,json};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+pub type CookieJars = HashMap<String,Arc<CookieJar>>;
+
+#[derive(Debug,Clone,PartialEq)]
+struct Cookie{
+    name: String,
+    value: String,
+    domain: String,
+    // Cookies without a Domain attribute are only sent to the exact host that set them
+    host_only: bool,
+    path: String,
+    secure: bool,
+    // Session cookies have no expiry
+    expires: Option<SystemTime>
+}
+
+impl Cookie{
+    // RFC 6265 5.2, attributes that don't parse are ignored
+    fn parse(header: &str, uri: &hyper::Uri, now: SystemTime) -> Option<Cookie>{
+        let host = uri.host()?.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name,value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty(){
+            return None
+        }
+        let mut cookie = Cookie{
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri.path()),
+            secure: false,
+            expires: None
+        };
+        let mut max_age : Option<i64> = None;
+        for attribute in parts{
+            let (key,val) = match attribute.split_once('='){
+                Some((k,v)) => (k.trim(),v.trim()),
+                None => (attribute.trim(),"")
+            };
+            match key.to_ascii_lowercase().as_str(){
+                "domain" if !val.is_empty() => {
+                    let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    // A host may set cookies for itself and its parent domains, never for other hosts
+                    if !domain_matches(&host,&domain){
+                        return None
+                    }
+                    cookie.host_only = host == domain;
+                    cookie.domain = domain;
+                },
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok().or(max_age),
+                "expires" if max_age.is_none() => cookie.expires = parse_cookie_date(val).or(cookie.expires),
+                _ => ()
+            }
+        }
+        // Max-Age has precedence over Expires, a non-positive value expires the cookie at once
+        if let Some(age) = max_age{
+            cookie.expires = match age > 0{
+                true => Some(now + Duration::from_secs(age as u64)),
+                false => Some(UNIX_EPOCH)
+            };
+        }
+        Some(cookie)
+    }
+    fn is_expired(&self, now: SystemTime) -> bool{
+        self.expires.is_some_and(|at| at <= now)
+    }
+    fn matches(&self, uri: &hyper::Uri) -> bool{
+        let host = match uri.host(){
+            Some(h) => h.to_ascii_lowercase(),
+            None => return false
+        };
+        let domain_ok = match self.host_only{
+            true => host == self.domain,
+            false => domain_matches(&host,&self.domain)
+        };
+        domain_ok && path_matches(uri.path(),&self.path) && (!self.secure || uri.scheme_str() == Some("https"))
+    }
+    fn to_json(&self) -> Value{
+        json!({
+            "name": self.name,
+            "value": self.value,
+            "domain": self.domain,
+            "host_only": self.host_only,
+            "path": self.path,
+            "secure": self.secure,
+            "expires": self.expires.map(|at| at.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs())
+        })
+    }
+    fn from_json(input: &Value) -> Option<Cookie>{
+        let text = |key: &str| input.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+        Some(Cookie{
+            name: text("name")?,
+            value: text("value")?,
+            domain: text("domain")?,
+            host_only: input.get("host_only").and_then(|v| v.as_bool()).unwrap_or(true),
+            path: text("path").unwrap_or("/".to_string()),
+            secure: input.get("secure").and_then(|v| v.as_bool()).unwrap_or(false),
+            expires: input.get("expires").and_then(|v| v.as_u64()).map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        })
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool{
+    if host == domain{
+        return true
+    }
+    // Suffix matching only applies to host names, not to IP addresses
+    host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.') && host.parse::<std::net::IpAddr>().is_err()
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool{
+    request_path == cookie_path || (request_path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+// The directory of the request path is the default path of a cookie
+fn default_path(request_path: &str) -> String{
+    match request_path.rfind('/'){
+        Some(0) | None => "/".to_string(),
+        Some(i) => request_path[..i].to_string()
+    }
+}
+
+// Cookie dates often separate the date parts with dashes instead of spaces
+fn parse_cookie_date(input: &str) -> Option<SystemTime>{
+    httpdate::parse_http_date(input).ok()
+        .or_else(|| httpdate::parse_http_date(&input.replace('-'," ")).ok())
+}
+
+// Session cookies are credentials, so the jar file is only readable by its owner
+fn create_private(path: &Path) -> std::io::Result<std::fs::File>{
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+// Written to a temporary file first so that a crash never leaves a truncated jar behind
+fn write_jar(path: &Path, contents: &str, revision: u64, written: &Mutex<u64>){
+    let mut last = match written.lock(){
+        Ok(guard) => guard,
+        Err(_) => return
+    };
+    // A newer jar has already been written by another request
+    if *last >= revision{
+        return
+    }
+    let temporary = path.with_extension("tmp");
+    let _ = std::fs::remove_file(&temporary);
+    let result = match path.parent(){
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(())
+    }
+        .and_then(|_| create_private(&temporary))
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|_| std::fs::rename(&temporary,path));
+    match result{
+        Ok(_) => *last = revision,
+        Err(e) => eprintln!("Cookie file {} could not be written: {e}",path.display())
+    }
+}
+
+#[derive(Debug)]
+pub struct CookieJar{
+    cookies: Mutex<Vec<Cookie>>,
+    persist: Option<PathBuf>,
+    // Counts the changes, so that writes finishing out of order keep the latest jar
+    revision: AtomicU64,
+    written: Arc<Mutex<u64>>
+}
+
+impl CookieJar{
+    pub fn new(persist: Option<PathBuf>) -> Self{
+        let cookies = match &persist{
+            Some(path) => CookieJar::load(path),
+            None => vec![]
+        };
+        CookieJar{ cookies: Mutex::new(cookies), persist, revision: AtomicU64::new(0), written: Arc::new(Mutex::new(0)) }
+    }
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        match table.try_parse_string("persist"){
+            Ok(path) => Ok(CookieJar::new(Some(PathBuf::from(path)))),
+            Err(ServerConfigError::MissingKey) => Ok(CookieJar::new(None)),
+            Err(e) => Err(e)
+        }
+    }
+    pub fn try_parse_sessions(table: &config::Map<String, config::Value>) -> CookieJars{
+        let mut map = HashMap::new();
+        for (name,val) in table.iter(){
+            match CookieJar::try_parse(val){
+                Ok(jar) => {
+                    map.insert(name.clone(),Arc::new(jar));
+                },
+                Err(e) => eprintln!("Session '{name}' is ignored: {e}")
+            }
+        }
+        map
+    }
+    fn load(path: &PathBuf) -> Vec<Cookie>{
+        let now = SystemTime::now();
+        match std::fs::read(path){
+            Ok(bytes) => match serde_json::from_slice::<Value>(&bytes){
+                Ok(Value::Array(list)) => list.iter()
+                    .filter_map(Cookie::from_json)
+                    .filter(|c| !c.is_expired(now))
+                    .collect(),
+                _ => {
+                    eprintln!("Ignoring unreadable cookie file: {}",path.display());
+                    vec![]
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                eprintln!("Cookie file {} could not be read: {e}",path.display());
+                vec![]
+            }
+        }
+    }
+    // The file is written on the blocking pool, the jar itself is not locked meanwhile
+    async fn save(&self, contents: String, revision: u64){
+        let path = match &self.persist{
+            Some(p) => p.clone(),
+            None => return
+        };
+        let written = self.written.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || write_jar(&path,&contents,revision,&written)).await{
+            eprintln!("Cookie file could not be written: {e}");
+        }
+    }
+    // Value for the Cookie header, longer paths are listed first
+    pub fn cookie_header(&self, uri: &hyper::Uri) -> Option<String>{
+        let now = SystemTime::now();
+        let mut cookies = match self.cookies.lock(){
+            Ok(guard) => guard,
+            Err(_) => return None
+        };
+        cookies.retain(|c| !c.is_expired(now));
+        let mut matching : Vec<&Cookie> = cookies.iter().filter(|c| c.matches(uri)).collect();
+        if matching.is_empty(){
+            return None
+        }
+        matching.sort_by(|a,b| b.path.len().cmp(&a.path.len()));
+        Some(matching.iter().map(|c| format!("{}={}",c.name,c.value)).collect::<Vec<String>>().join("; "))
+    }
+    // Stores the cookies of a response, a cookie replaces the one with the same name, domain and path
+    pub async fn store(&self, uri: &hyper::Uri, headers: &hyper::HeaderMap){
+        let now = SystemTime::now();
+        let received : Vec<Cookie> = headers.get_all(hyper::header::SET_COOKIE).iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| Cookie::parse(v,uri,now))
+            .collect();
+        if received.is_empty(){
+            return
+        }
+        let snapshot = {
+            let mut cookies = match self.cookies.lock(){
+                Ok(guard) => guard,
+                Err(_) => return
+            };
+            for cookie in received.into_iter(){
+                cookies.retain(|c| !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path));
+                if !cookie.is_expired(now){
+                    cookies.push(cookie);
+                }
+            }
+            cookies.retain(|c| !c.is_expired(now));
+            match self.persist.is_some(){
+                true => Some((Value::Array(cookies.iter().map(|c| c.to_json()).collect()).to_string(),self.revision.fetch_add(1,Ordering::SeqCst) + 1)),
+                false => None
+            }
+        };
+        if let Some((contents,revision)) = snapshot{
+            self.save(contents,revision).await;
+        }
+    }
+}
@@ -1,7 +1,10 @@
 #![deny(warnings)]
+use std::path::PathBuf;
+use std::sync::{Arc,RwLock};
+use std::time::SystemTime;
 use super::{resource::TryParseTypedValue};
 use crate::settings::ServerConfigError;
-use crate::support::cryptea;
+use crate::support::secrets::SecretKey;
 
 #[derive(Debug,Clone)]
 pub enum CredentialsMode{
@@ -9,29 +12,149 @@ pub enum CredentialsMode{
     Encoded
 }
 
-#[derive(Debug,Clone)]
-pub struct ResourceCredentials{
-    key: Vec<u8>,
-    header: String,
-    mode: CredentialsMode
+// Secrets are either stored as is or encrypted with the secret key
+pub fn decode_secret(value: &[u8], mode: &CredentialsMode, key: &SecretKey) -> Result<String,ServerConfigError>{
+    match mode{
+        CredentialsMode::Plain => match String::from_utf8(value.to_vec()){
+            Ok(s) => Ok(s),
+            Err(_) => Err(ServerConfigError::DecodeError)
+        },
+        CredentialsMode::Encoded => match key.decrypt(value){
+            Ok(s) => Ok(s),
+            Err(e) => {
+                println!("{e}");
+                Err(ServerConfigError::DecodeError)
+            }
+        }
+    }
 }
 
-impl ResourceCredentials{
-    pub fn derive_key(&self,key: &str) -> Result<String,ServerConfigError>{
-        match self.mode{
-            CredentialsMode::Plain => match String::from_utf8(self.key.clone()){
-                Ok(s) => Ok(s),
-                Err(_) => Err(ServerConfigError::DecodeError)
-            },
-            CredentialsMode::Encoded => match cryptea::decode(&self.key,key){
-                Ok(s) => Ok(s),
+// Where the secret value comes from, environment values are read once at startup
+#[derive(Debug)]
+pub enum SecretSource{
+    Inline(Vec<u8>),
+    Env(Vec<u8>),
+    File(SecretFile)
+}
+
+impl SecretSource{
+    // The secret is set with one of "<key>", "<key>_env" and "<key>_file", MissingKey if none of them is
+    pub fn try_parse(table: &config::Map<String, config::Value>, key: &str) -> Result<Self,ServerConfigError>{
+        let (env_key,file_key) = (format!("{key}_env"),format!("{key}_file"));
+        let sources = [table.try_parse_string(key),table.try_parse_string(&env_key),table.try_parse_string(&file_key)];
+        if sources.iter().filter(|s| s.is_ok()).count() > 1{
+            eprintln!("Only one of {key}, {env_key} and {file_key} can be set");
+            return Err(ServerConfigError::InvalidValue)
+        }
+        match sources{
+            [Ok(k),Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey)] => Ok(SecretSource::Inline(k.into_bytes())),
+            [Err(ServerConfigError::MissingKey),Ok(name),Err(ServerConfigError::MissingKey)] => match std::env::var(&name){
+                Ok(v) if !v.is_empty() => Ok(SecretSource::Env(v.into_bytes())),
+                Ok(_) => {
+                    eprintln!("Credentials environment variable {name} is empty");
+                    Err(ServerConfigError::InvalidValue)
+                },
                 Err(e) => {
-                    println!("{e}");
-                    Err(ServerConfigError::DecodeError)
+                    eprintln!("Credentials environment variable {name} could not be read: {e}");
+                    Err(ServerConfigError::InvalidValue)
                 }
+            },
+            [Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey),Ok(path)] => Ok(SecretSource::File(SecretFile::open(PathBuf::from(path))?)),
+            [Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey)] => Err(ServerConfigError::MissingKey),
+            _ => {
+                eprintln!("{key}, {env_key} and {file_key} must be strings");
+                Err(ServerConfigError::InvalidValue)
+            }
+        }
+    }
+    pub fn value(&self) -> Vec<u8>{
+        match self{
+            SecretSource::Inline(bytes) | SecretSource::Env(bytes) => bytes.clone(),
+            SecretSource::File(file) => file.value()
+        }
+    }
+    // Secrets outside of the config file are usually stored as is
+    pub fn default_mode(&self) -> CredentialsMode{
+        match self{
+            SecretSource::Inline(_) => CredentialsMode::Encoded,
+            _ => CredentialsMode::Plain
+        }
+    }
+}
+
+// A secret file is read again when its modification time or size changes
+#[derive(Debug)]
+pub struct SecretFile{
+    path: PathBuf,
+    current: RwLock<(Option<(SystemTime,u64)>,Vec<u8>)>
+}
+
+impl SecretFile{
+    fn stamp(path: &PathBuf) -> Option<(SystemTime,u64)>{
+        std::fs::metadata(path).ok().and_then(|m| m.modified().ok().map(|t| (t,m.len())))
+    }
+    // Trailing newlines are not part of the secret
+    fn read(path: &PathBuf) -> std::io::Result<Vec<u8>>{
+        let mut bytes = std::fs::read(path)?;
+        while bytes.last().is_some_and(|b| b.is_ascii_whitespace()){
+            bytes.pop();
+        }
+        Ok(bytes)
+    }
+    fn open(path: PathBuf) -> Result<Self,ServerConfigError>{
+        let stamp = SecretFile::stamp(&path);
+        match SecretFile::read(&path){
+            Ok(bytes) if !bytes.is_empty() => Ok(SecretFile{ path, current: RwLock::new((stamp,bytes)) }),
+            Ok(_) => {
+                eprintln!("Credentials file {} is empty",path.display());
+                Err(ServerConfigError::InvalidValue)
+            },
+            Err(e) => {
+                eprintln!("Credentials file {} could not be read: {e}",path.display());
+                Err(ServerConfigError::InvalidValue)
+            }
+        }
+    }
+    // The previous value is kept if the file can't be read, e.g. while it is being replaced
+    fn value(&self) -> Vec<u8>{
+        let stamp = SecretFile::stamp(&self.path);
+        if let Ok(guard) = self.current.read(){
+            if stamp.is_none() || guard.0 == stamp{
+                return guard.1.clone()
             }
         }
+        match (SecretFile::read(&self.path),self.current.write()){
+            (Ok(bytes),Ok(mut guard)) if !bytes.is_empty() => {
+                println!("Credentials file {} changed, using the new value",self.path.display());
+                *guard = (stamp,bytes.clone());
+                bytes
+            },
+            (_,Ok(guard)) => guard.1.clone(),
+            (_,Err(_)) => vec![]
+        }
+    }
+}
 
+#[derive(Debug)]
+pub struct ResourceCredentials{
+    source: SecretSource,
+    header: String,
+    mode: CredentialsMode,
+    prefix: Option<String>
+}
+
+impl ResourceCredentials{
+    pub fn derive_key(&self,key: &SecretKey) -> Result<String,ServerConfigError>{
+        let value = decode_secret(&self.source.value(),&self.mode,key)?;
+        match &self.prefix{
+            Some(prefix) => Ok(format!("{prefix}{value}")),
+            None => Ok(value)
+        }
+    }
+    pub async fn prepare(&self, key: &Arc<SecretKey>){
+        if let CredentialsMode::Encoded = self.mode{
+            key.prepare(&self.source.value()).await
+        }
     }
     pub fn header(&self) -> &str{
         &self.header
@@ -44,19 +167,11 @@ impl ResourceCredentials{
                 return Err(ServerConfigError::InvalidValue)
             }
         };
-        let resource_key = match table.try_parse_string("value"){
-            Ok(k) => {
-                let s : Option<Vec<u8>> = match k.as_bytes().try_into(){
-                    Ok(slice) => Some(slice),
-                    Err(_) => None
-                };
-                s
-            },
-            Err(_) => None
+        let source = match SecretSource::try_parse(&table,"value"){
+            Ok(source) => source,
+            Err(ServerConfigError::MissingKey) => return Err(ServerConfigError::NotAvailable),
+            Err(e) => return Err(e)
         };
-        if resource_key.is_none(){
-            return Err(ServerConfigError::NotAvailable)
-        }
         let resource_header = match table.try_parse_string("header"){
             Ok(k) => {
                 match k.len() > 4 && k.len() < 50 { // arbitrary restriction for header length
@@ -73,17 +188,23 @@ impl ResourceCredentials{
                     _ => CredentialsMode::Encoded
                 }
             },
-            Err(_) => CredentialsMode::Encoded
+            Err(_) => source.default_mode()
+        };
+        let prefix = match table.try_parse_string("prefix"){
+            Ok(p) => Some(p),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
         };
         match resource_header {
             Some(header) => Ok(ResourceCredentials{
-                key: resource_key.unwrap(),
+                source,
                 header: header,
-                mode: key_mode
+                mode: key_mode,
+                prefix
             }),
             None => Err(ServerConfigError::NotAvailable)
         }
         
     }
     
-}
\ No newline at end of file
+}
@@ -15,6 +15,131 @@ impl std::fmt::Display for HeaderError {
         }
     }
 }
+// Connection specific headers that must never be forwarded between hops
+pub(crate) static HOP_BY_HOP: [&str;10] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length"
+];
+
+pub(crate) fn is_hop_by_hop(name: &str) -> bool{
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+}
+
+// Parses a list of header names, connection specific headers are refused
+pub(crate) fn parse_header_names(input: &config::Value) -> Result<Vec<http::HeaderName>,HeaderError>{
+    let list = match input.clone().into_array(){
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(HeaderError::ParseError)
+        }
+    };
+    let mut names = vec![];
+    for val in list.into_iter(){
+        let name = match val.into_string(){
+            Ok(n) => n,
+            Err(_) => return Err(HeaderError::ParseError)
+        };
+        if is_hop_by_hop(&name){
+            eprintln!("Hop-by-hop header '{name}' can't be forwarded");
+            return Err(HeaderError::ParseError)
+        }
+        match http::HeaderName::from_bytes(name.as_bytes()){
+            Ok(n) => names.push(n),
+            Err(_) => return Err(HeaderError::ParseError)
+        }
+    }
+    Ok(names)
+}
+
+#[derive(Debug)]
+pub(crate) struct ForwardHeader{
+    name: http::HeaderName,
+    target: http::HeaderName,
+    default: Option<http::HeaderValue>
+}
+
+impl ForwardHeader{
+    fn new(name: &str, target: Option<&str>, default: Option<&str>) -> Result<Self,HeaderError>{
+        if is_hop_by_hop(name) || target.is_some_and(is_hop_by_hop){
+            eprintln!("Hop-by-hop header '{name}' can't be forwarded");
+            return Err(HeaderError::ParseError)
+        }
+        let parse_name = |n: &str| http::HeaderName::from_bytes(n.as_bytes()).map_err(|_| HeaderError::ParseError);
+        let name = parse_name(name)?;
+        let target = match target{
+            Some(t) => parse_name(t)?,
+            None => name.clone()
+        };
+        let default = match default{
+            Some(d) => match http::HeaderValue::from_str(d){
+                Ok(v) => Some(v),
+                Err(_) => return Err(HeaderError::ParseError)
+            },
+            None => None
+        };
+        Ok(ForwardHeader{ name, target, default })
+    }
+    // Accepts either a plain header name or a table of { name, rename, default }
+    fn try_parse(input: &config::Value) -> Result<Self,HeaderError>{
+        if let Ok(name) = input.clone().into_string(){
+            return ForwardHeader::new(&name,None,None)
+        }
+        let table = match input.clone().into_table(){
+            Ok(t) => t,
+            Err(_) => return Err(HeaderError::ParseError)
+        };
+        let get = |key: &str| table.get(key).and_then(|v| v.clone().into_string().ok());
+        match get("name"){
+            Some(name) => ForwardHeader::new(&name,get("rename").as_deref(),get("default").as_deref()),
+            None => Err(HeaderError::ParseError)
+        }
+    }
+    // Names in reserved are already set on the upstream request, forwarding them would send duplicate values
+    pub(crate) fn parse_list(input: &config::Value, reserved: &[String]) -> Result<Vec<ForwardHeader>,HeaderError>{
+        let list = match input.clone().into_array(){
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(HeaderError::ParseError)
+            }
+        };
+        let headers = list.iter().map(ForwardHeader::try_parse).collect::<Result<Vec<ForwardHeader>,HeaderError>>()?;
+        if let Some(fh) = headers.iter().find(|fh| reserved.iter().any(|r| fh.target.as_str().eq_ignore_ascii_case(r))){
+            eprintln!("Header '{}' is already set by the resource and can't be forwarded",fh.target);
+            return Err(HeaderError::ParseError)
+        }
+        // Two forwarded headers with the same target would overwrite each other
+        match headers.iter().enumerate().find(|(i,fh)| headers[..*i].iter().any(|other| other.target == fh.target)){
+            Some((_,fh)) => {
+                eprintln!("Header '{}' is the target of more than one forwarded header",fh.target);
+                Err(HeaderError::ParseError)
+            },
+            None => Ok(headers)
+        }
+    }
+    // Picks the value from incoming request unless the client marked it connection specific
+    pub(crate) fn resolve(&self, incoming: &hyper::HeaderMap) -> Option<(http::HeaderName,http::HeaderValue)>{
+        let connection_scoped = incoming.get_all(http::header::CONNECTION).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(self.name.as_str()));
+        let value = match incoming.get(&self.name){
+            Some(v) if !connection_scoped => Some(v.clone()),
+            _ => self.default.clone()
+        };
+        value.map(|v| (self.target.clone(),v))
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct HeaderSet{
     headers: Vec<Header>
@@ -0,0 +1,182 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::time::{Duration,Instant};
+use tokio::sync::{Semaphore,SemaphorePermit};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+pub type HostLimits = HashMap<String,Arc<UpstreamLimiter>>;
+
+#[derive(Debug)]
+pub enum LimitError{
+    QueueFull,
+    Timeout
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self{
+            LimitError::QueueFull => write!(f, "Upstream request queue is full"),
+            LimitError::Timeout => write!(f, "Timed out while waiting in upstream request queue")
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket{
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant
+}
+
+impl TokenBucket{
+    // Reserves a token and returns how long the caller has to wait before using it.
+    // Tokens can go negative so that waiting callers are served in order.
+    fn reserve(&mut self) -> Duration{
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+        self.tokens -= 1.0;
+        match self.tokens < 0.0{
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO
+        }
+    }
+    fn refund(&mut self){
+        self.tokens = (self.tokens + 1.0).min(self.burst);
+    }
+}
+
+// Gives the reserved token back if the request gives up waiting before it is sent
+struct Reservation<'a>{
+    bucket: Option<&'a Mutex<TokenBucket>>,
+    used: bool
+}
+
+impl Drop for Reservation<'_>{
+    fn drop(&mut self){
+        if self.used{
+            return
+        }
+        if let Some(Ok(mut bucket)) = self.bucket.map(|b| b.lock()){
+            bucket.refund();
+        }
+    }
+}
+
+// Keeps the queue counter correct even if the waiting future is dropped
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_>{
+    fn drop(&mut self){
+        self.0.fetch_sub(1,Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+pub struct UpstreamLimiter{
+    concurrency: Option<Semaphore>,
+    bucket: Option<Mutex<TokenBucket>>,
+    max_queue: usize,
+    queue_timeout: Duration,
+    queued: AtomicUsize
+}
+
+impl UpstreamLimiter{
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let concurrency = match table.try_parse_u64("max_concurrent"){
+            Ok(0) => return Err(ServerConfigError::InvalidValue),
+            Ok(n) => Some(Semaphore::new(n.min(Semaphore::MAX_PERMITS as u64) as usize)),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        let bucket = match table.try_parse_f64("rate_limit"){
+            Ok(rate) if rate > 0.0 => {
+                let burst = match table.try_parse_f64("burst"){
+                    Ok(b) if b >= 1.0 => b,
+                    Ok(_) => return Err(ServerConfigError::InvalidValue),
+                    Err(ServerConfigError::MissingKey) => rate.max(1.0),
+                    Err(e) => return Err(e)
+                };
+                Some(Mutex::new(TokenBucket{ rate, burst, tokens: burst, updated: Instant::now() }))
+            },
+            Ok(_) => return Err(ServerConfigError::InvalidValue),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        let max_queue = match table.try_parse_u64("max_queue"){
+            Ok(n) => n as usize,
+            Err(ServerConfigError::MissingKey) => 32,
+            Err(e) => return Err(e)
+        };
+        let queue_timeout = match table.try_parse_u64("queue_timeout"){
+            Ok(secs) => Duration::from_secs(secs),
+            Err(ServerConfigError::MissingKey) => Duration::from_secs(30),
+            Err(e) => return Err(e)
+        };
+        Ok(UpstreamLimiter{
+            concurrency,
+            bucket,
+            max_queue,
+            queue_timeout,
+            queued: AtomicUsize::new(0)
+        })
+    }
+    pub fn try_parse_hosts(table: &config::Map<String, config::Value>) -> HostLimits{
+        let mut map = HashMap::new();
+        for (host,val) in table.iter(){
+            match UpstreamLimiter::try_parse(val){
+                Ok(limiter) => {
+                    map.insert(host.to_lowercase(),Arc::new(limiter));
+                },
+                Err(e) => eprintln!("Upstream limits for '{host}' are ignored: {e}")
+            }
+        }
+        map
+    }
+    // Waits until the request is allowed to proceed. The returned permit must be held until the request completes.
+    pub async fn acquire(&self) -> Result<Option<SemaphorePermit<'_>>,LimitError>{
+        if self.queued.fetch_add(1,Ordering::SeqCst) >= self.max_queue{
+            self.queued.fetch_sub(1,Ordering::SeqCst);
+            return Err(LimitError::QueueFull)
+        }
+        let _slot = QueueSlot(&self.queued);
+        let wait = async {
+            let mut reservation = Reservation{ bucket: None, used: false };
+            let delay = match &self.bucket{
+                Some(bucket) => match bucket.lock(){
+                    Ok(mut b) => {
+                        reservation.bucket = Some(bucket);
+                        b.reserve()
+                    },
+                    Err(_) => Duration::ZERO
+                },
+                None => Duration::ZERO
+            };
+            if !delay.is_zero(){
+                tokio::time::sleep(delay).await;
+            }
+            let permit = match &self.concurrency{
+                Some(semaphore) => semaphore.acquire().await.ok(),
+                None => None
+            };
+            reservation.used = true;
+            permit
+        };
+        match tokio::time::timeout(self.queue_timeout,wait).await{
+            Ok(permit) => Ok(permit),
+            Err(_) => Err(LimitError::Timeout)
+        }
+    }
+}
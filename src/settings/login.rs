@@ -0,0 +1,166 @@
+#![deny(warnings)]
+use std::sync::Arc;
+use std::time::{Duration,Instant};
+use tokio::sync::{Mutex,MutexGuard};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+#[derive(Debug)]
+enum SessionSource{
+    // JSON pointer to the token in the login response body
+    Pointer(String),
+    // Name of the cookie set by the login response
+    Cookie(String)
+}
+
+// Latest response of a login resource, every resource logging in through it reads its session from here
+#[derive(Debug)]
+pub struct LoginResponse{
+    headers: hyper::HeaderMap,
+    body: bytes::Bytes,
+    received: Instant
+}
+
+impl LoginResponse{
+    pub fn new(headers: hyper::HeaderMap, body: bytes::Bytes) -> Self{
+        LoginResponse{ headers, body, received: Instant::now() }
+    }
+}
+
+// One per login resource, so that a new login doesn't end the session the other resources are using
+pub type SharedLogin = Arc<Mutex<Option<LoginResponse>>>;
+
+#[derive(Debug,Clone)]
+pub struct Session{
+    pub value: String,
+    expires: Option<Instant>
+}
+
+impl Session{
+    pub fn is_valid(&self) -> bool{
+        match self.expires{
+            Some(at) => Instant::now() < at,
+            None => true
+        }
+    }
+}
+
+// Finds a cookie from the Set-Cookie headers, returns its value and Max-Age
+fn find_cookie(headers: &hyper::HeaderMap, name: &str) -> Option<(String,Option<Duration>)>{
+    for value in headers.get_all(hyper::header::SET_COOKIE).iter(){
+        let value = match value.to_str(){
+            Ok(v) => v,
+            Err(_) => continue
+        };
+        let mut attributes = value.split(';');
+        match attributes.next().and_then(|pair| pair.split_once('=')){
+            Some((key,cookie)) if key.trim() == name => {
+                let max_age = attributes
+                    .filter_map(|a| a.split_once('='))
+                    .find(|(key,_)| key.trim().eq_ignore_ascii_case("max-age"))
+                    .and_then(|(_,age)| age.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                return Some((cookie.trim().to_string(),max_age))
+            },
+            _ => continue
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct SessionAuth{
+    pub login: String,
+    source: SessionSource,
+    header: String,
+    template: String,
+    ttl: Option<Duration>,
+    shared: SharedLogin
+}
+
+impl SessionAuth{
+    pub fn try_parse(login: String, input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let source = match (table.try_parse_string("token"),table.try_parse_string("cookie")){
+            (Ok(pointer),Err(ServerConfigError::MissingKey)) => match pointer.starts_with('/'){
+                true => SessionSource::Pointer(pointer),
+                false => SessionSource::Pointer(format!("/{pointer}"))
+            },
+            (Err(ServerConfigError::MissingKey),Ok(cookie)) => SessionSource::Cookie(cookie),
+            (Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey)) => return Err(ServerConfigError::MissingKey),
+            (_,_) => return Err(ServerConfigError::InvalidValue)
+        };
+        let header = match table.try_parse_string("header"){
+            Ok(k) => match http::HeaderName::from_bytes(k.as_bytes()){
+                Ok(_) => k,
+                Err(_) => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => match &source{
+                SessionSource::Pointer(_) => http::header::AUTHORIZATION.to_string(),
+                SessionSource::Cookie(_) => http::header::COOKIE.to_string()
+            },
+            Err(e) => return Err(e)
+        };
+        let template = match table.try_parse_string("template"){
+            Ok(t) if t.contains("{token}") => t,
+            Ok(_) => return Err(ServerConfigError::InvalidValue),
+            Err(ServerConfigError::MissingKey) => match &source{
+                SessionSource::Pointer(_) => "Bearer {token}".to_string(),
+                SessionSource::Cookie(name) => format!("{name}={{token}}")
+            },
+            Err(e) => return Err(e)
+        };
+        let ttl = match table.try_parse_u64("ttl"){
+            Ok(0) => return Err(ServerConfigError::InvalidValue),
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        Ok(SessionAuth{ login, source, header, template, ttl, shared: Arc::new(Mutex::new(None)) })
+    }
+    pub fn header(&self) -> &str{
+        &self.header
+    }
+    pub fn share(&mut self, shared: SharedLogin){
+        self.shared = shared;
+    }
+    // Holding the lock while logging in keeps concurrent requests from opening several sessions
+    pub async fn lock(&self) -> MutexGuard<'_,Option<LoginResponse>>{
+        self.shared.lock().await
+    }
+    // Drops the login only if it hasn't been replaced since its session was rejected
+    pub async fn invalidate(&self, rejected: &str){
+        let mut guard = self.shared.lock().await;
+        if guard.as_ref().and_then(|response| self.establish(response)).is_some_and(|s| s.value == rejected){
+            *guard = None;
+        }
+    }
+    // Builds the session from the login response, a cookie Max-Age shorter than the ttl wins
+    pub fn establish(&self, response: &LoginResponse) -> Option<Session>{
+        let (token,max_age) = match &self.source{
+            SessionSource::Pointer(pointer) => {
+                let response : serde_json::Value = serde_json::from_slice(&response.body).ok()?;
+                match response.pointer(pointer)?{
+                    serde_json::Value::String(s) if !s.is_empty() => (s.clone(),None),
+                    serde_json::Value::Number(n) => (n.to_string(),None),
+                    _ => return None
+                }
+            },
+            SessionSource::Cookie(name) => find_cookie(&response.headers,name)?
+        };
+        let lifetime = match (self.ttl,max_age){
+            (Some(ttl),Some(age)) => Some(ttl.min(age)),
+            (ttl,age) => ttl.or(age)
+        };
+        Some(Session{
+            value: self.template.replace("{token}",&token),
+            expires: lifetime.map(|l| response.received + l)
+        })
+    }
+}
@@ -0,0 +1,180 @@
+#![deny(warnings)]
+use std::sync::Arc;
+use std::time::{Duration,Instant};
+use base64::{Engine,engine::general_purpose};
+use bytes::Bytes;
+use http_body_util::Full;
+use percent_encoding::{AsciiSet,NON_ALPHANUMERIC,utf8_percent_encode};
+use tokio::sync::{Mutex,MutexGuard};
+use super::resource::TryParseTypedValue;
+use super::qualifieduri::QualifiedUri;
+use super::credentials::{CredentialsMode,SecretSource,decode_secret};
+use crate::settings::ServerConfigError;
+use crate::support::secrets::SecretKey;
+
+static FORM_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+// Tokens are refreshed this long before the endpoint says they expire
+const EXPIRY_MARGIN : Duration = Duration::from_secs(30);
+// Used when the token response doesn't tell the lifetime of the token
+const DEFAULT_LIFETIME : Duration = Duration::from_secs(300);
+
+#[derive(Debug,Clone,PartialEq)]
+enum ClientAuth{
+    // Client id and secret in a basic Authorization header
+    Basic,
+    // Client id and secret as form fields of the token request
+    Body
+}
+
+#[derive(Debug,Clone)]
+pub struct AccessToken{
+    pub value: String,
+    refresh_at: Instant
+}
+
+impl AccessToken{
+    pub fn is_valid(&self) -> bool{
+        Instant::now() < self.refresh_at
+    }
+}
+
+#[derive(Debug)]
+pub struct OAuth2Credentials{
+    token_url: hyper::Uri,
+    client_id: String,
+    client_secret: SecretSource,
+    secret_mode: CredentialsMode,
+    secret_key: Arc<SecretKey>,
+    scope: Option<String>,
+    client_auth: ClientAuth,
+    header: String,
+    token: Mutex<Option<AccessToken>>
+}
+
+impl OAuth2Credentials{
+    pub fn try_parse(input: &config::Value, disallowed_port: u16, secret_key: Arc<SecretKey>) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let token_url = match QualifiedUri::try_build(table.try_parse_string("token_url")?,disallowed_port){
+            Ok(uri) if !uri.is_templated() => uri.uri(),
+            Ok(_) => return Err(ServerConfigError::InvalidValue),
+            Err(e) => return Err(e)
+        };
+        let client_id = table.try_parse_string("client_id")?;
+        // client_secret_env and client_secret_file work like value_env and value_file of other credentials
+        let client_secret = SecretSource::try_parse(&table,"client_secret")?;
+        let secret_mode = match table.try_parse_string("secret_mode"){
+            Ok(k) => match k.as_str(){
+                "plain" => CredentialsMode::Plain,
+                _ => CredentialsMode::Encoded
+            },
+            Err(_) => client_secret.default_mode()
+        };
+        let scope = match table.try_parse_string("scope"){
+            Ok(s) => Some(s),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        let client_auth = match table.try_parse_string("client_auth"){
+            Ok(k) => match k.as_str(){
+                "basic" => ClientAuth::Basic,
+                "body" => ClientAuth::Body,
+                _ => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => ClientAuth::Basic,
+            Err(e) => return Err(e)
+        };
+        let header = match table.try_parse_string("header"){
+            Ok(k) => match http::HeaderName::from_bytes(k.as_bytes()){
+                Ok(_) => k,
+                Err(_) => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => http::header::AUTHORIZATION.to_string(),
+            Err(e) => return Err(e)
+        };
+        Ok(OAuth2Credentials{
+            token_url,
+            client_id,
+            client_secret,
+            secret_mode,
+            secret_key,
+            scope,
+            client_auth,
+            header,
+            token: Mutex::new(None)
+        })
+    }
+    pub fn header(&self) -> &str{
+        &self.header
+    }
+    // Holding the lock while the token is fetched keeps concurrent requests from all refreshing it
+    pub async fn lock(&self) -> MutexGuard<'_,Option<AccessToken>>{
+        self.token.lock().await
+    }
+    // Drops the token only if it hasn't been replaced since it was rejected
+    pub async fn invalidate(&self, rejected: &str){
+        let mut guard = self.token.lock().await;
+        if guard.as_ref().is_some_and(|t| t.value == rejected){
+            *guard = None;
+        }
+    }
+    pub async fn prepare_secret(&self){
+        if let CredentialsMode::Encoded = self.secret_mode{
+            self.secret_key.prepare(&self.client_secret.value()).await
+        }
+    }
+    pub fn token_request(&self, user_agent: &str) -> Result<hyper::Request<Full<Bytes>>,ServerConfigError>{
+        let secret = decode_secret(&self.client_secret.value(),&self.secret_mode,&self.secret_key)?;
+        let mut fields = vec![("grant_type","client_credentials")];
+        if let Some(scope) = &self.scope{
+            fields.push(("scope",scope));
+        }
+        if self.client_auth == ClientAuth::Body{
+            fields.push(("client_id",&self.client_id));
+            fields.push(("client_secret",&secret));
+        }
+        let form = fields.iter()
+            .map(|(k,v)| format!("{}={}",k,utf8_percent_encode(v,FORM_VALUE)))
+            .collect::<Vec<String>>()
+            .join("&");
+        let mut builder = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(self.token_url.clone())
+            .header(http::header::USER_AGENT,user_agent)
+            .header(http::header::ACCEPT,"application/json")
+            .header(http::header::CONTENT_TYPE,"application/x-www-form-urlencoded");
+        if self.client_auth == ClientAuth::Basic{
+            // RFC 6749 2.3.1, both parts are form encoded before joining
+            let pair = format!("{}:{}",utf8_percent_encode(&self.client_id,FORM_VALUE),utf8_percent_encode(&secret,FORM_VALUE));
+            builder = builder.header(http::header::AUTHORIZATION,format!("Basic {}",general_purpose::STANDARD.encode(pair)));
+        }
+        match builder.body(Full::new(Bytes::from(form))){
+            Ok(req) => Ok(req),
+            Err(_) => Err(ServerConfigError::InvalidValue)
+        }
+    }
+    // Reads the token endpoint response, the token is refreshed shortly before it expires
+    pub fn parse_token(body: &[u8]) -> Option<AccessToken>{
+        let response : serde_json::Value = serde_json::from_slice(body).ok()?;
+        let value = response.get("access_token")?.as_str()?.to_string();
+        if let Some(kind) = response.get("token_type").and_then(|t| t.as_str()){
+            if !kind.eq_ignore_ascii_case("bearer"){
+                eprintln!("Unsupported token type: {kind}");
+                return None
+            }
+        }
+        let lifetime = match response.get("expires_in"){
+            Some(serde_json::Value::Number(n)) => n.as_u64().map(Duration::from_secs),
+            Some(serde_json::Value::String(s)) => s.parse::<u64>().ok().map(Duration::from_secs),
+            _ => None
+        }.unwrap_or(DEFAULT_LIFETIME);
+        let margin = EXPIRY_MARGIN.min(lifetime / 2);
+        Some(AccessToken{ value, refresh_at: Instant::now() + lifetime - margin })
+    }
+}
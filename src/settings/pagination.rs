@@ -0,0 +1,201 @@
+#![deny(warnings)]
+use serde_json::Value;
+use percent_encoding::{NON_ALPHANUMERIC,utf8_percent_encode};
+use super::resource::TryParseTypedValue;
+use super::qualifieduri::QueryParams;
+use crate::settings::ServerConfigError;
+
+#[derive(Debug)]
+enum PageMode{
+    // Pointer to the cursor in the response and the query parameter it is sent back in
+    Cursor{ field: String, param: String },
+    Link,
+    Offset{ offset_param: String, limit_param: String, page_size: u64 }
+}
+
+#[derive(Debug)]
+pub struct Pagination{
+    mode: PageMode,
+    items: String,
+    pub max_pages: u32
+}
+
+// Replaces or adds a single query parameter
+fn with_query_param(uri: &hyper::Uri, name: &str, value: &str) -> Option<hyper::Uri>{
+    let mut params = QueryParams::from_str(uri.query().unwrap_or(""));
+    params.map.insert(name.to_string(),Some(utf8_percent_encode(value,NON_ALPHANUMERIC).to_string()));
+    let path_and_query = format!("{}?{}",uri.path(),params.stringify().unwrap_or_default());
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    hyper::Uri::from_parts(parts).ok()
+}
+
+// Finds the rel="next" target of a Link header
+fn next_link(headers: &hyper::HeaderMap) -> Option<String>{
+    for value in headers.get_all(hyper::header::LINK).iter(){
+        let value = match value.to_str(){
+            Ok(v) => v,
+            Err(_) => continue
+        };
+        for link in value.split(','){
+            let mut parts = link.split(';');
+            let target = match parts.next().map(|t| t.trim()){
+                Some(t) if t.starts_with('<') && t.ends_with('>') => &t[1..t.len() - 1],
+                _ => continue
+            };
+            let is_next = parts.any(|param| match param.trim().split_once('='){
+                Some((key,rel)) => key.trim().eq_ignore_ascii_case("rel") && rel.trim_matches('"').split_whitespace().any(|r| r.eq_ignore_ascii_case("next")),
+                None => false
+            });
+            if is_next{
+                return Some(target.to_string())
+            }
+        }
+    }
+    None
+}
+
+// Resolves a link target against the current page (RFC 3986 5.2), fragments are ignored
+fn resolve_link(current: &hyper::Uri, target: &str) -> Option<hyper::Uri>{
+    let target = target.split('#').next().unwrap_or_default();
+    if target.contains("://"){
+        return target.parse().ok()
+    }
+    if let Some(rest) = target.strip_prefix("//"){
+        return format!("{}://{rest}",current.scheme_str()?).parse().ok()
+    }
+    let (path,query) = match target.split_once('?'){
+        Some((path,query)) => (path,Some(query)),
+        None => (target,None)
+    };
+    let merged = match path{
+        "" => current.path().to_string(),
+        p if p.starts_with('/') => p.to_string(),
+        p => match current.path().rsplit_once('/'){
+            Some((directory,_)) => format!("{directory}/{p}"),
+            None => format!("/{p}")
+        }
+    };
+    // Dot segments are removed so that "../items" stays below the host root
+    let mut segments : Vec<&str> = vec![];
+    let parts : Vec<&str> = merged.split('/').skip(1).collect();
+    for (i,segment) in parts.iter().enumerate(){
+        match *segment{
+            "." => if i == parts.len() - 1 { segments.push("") },
+            ".." => {
+                segments.pop();
+                if i == parts.len() - 1{
+                    segments.push("");
+                }
+            },
+            s => segments.push(s)
+        }
+    }
+    let mut path_and_query = format!("/{}",segments.join("/"));
+    match (query,path.is_empty()){
+        (Some(q),_) => path_and_query.push_str(&format!("?{q}")),
+        (None,true) => if let Some(q) = current.query(){
+            path_and_query.push_str(&format!("?{q}"));
+        },
+        (None,false) => ()
+    }
+    let mut parts = current.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    hyper::Uri::from_parts(parts).ok()
+}
+
+impl Pagination{
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let string_or = |key: &str, default: &str| match table.try_parse_string(key){
+            Ok(s) => Ok(s),
+            Err(ServerConfigError::MissingKey) => Ok(default.to_string()),
+            Err(e) => Err(e)
+        };
+        let mode = match table.try_parse_string("mode")?.as_str(){
+            "cursor" => PageMode::Cursor{
+                field: string_or("cursor_field","/next")?,
+                param: string_or("cursor_param","cursor")?
+            },
+            "link" => PageMode::Link,
+            "offset" => PageMode::Offset{
+                offset_param: string_or("offset_param","offset")?,
+                limit_param: string_or("limit_param","limit")?,
+                page_size: match table.try_parse_u64("page_size"){
+                    Ok(0) => return Err(ServerConfigError::InvalidValue),
+                    Ok(n) => n,
+                    Err(ServerConfigError::MissingKey) => 100,
+                    Err(e) => return Err(e)
+                }
+            },
+            _ => return Err(ServerConfigError::InvalidValue)
+        };
+        let items = string_or("items","")?;
+        let max_pages = match table.try_parse_u64("max_pages"){
+            Ok(0) => return Err(ServerConfigError::InvalidValue),
+            Ok(n) => n.min(u32::MAX as u64) as u32,
+            Err(ServerConfigError::MissingKey) => 10,
+            Err(e) => return Err(e)
+        };
+        Ok(Pagination{ mode, items, max_pages })
+    }
+    // Offset pagination sends the page window already with the first request
+    pub fn first_uri(&self, uri: &hyper::Uri) -> Option<hyper::Uri>{
+        match &self.mode{
+            PageMode::Offset{ offset_param, limit_param, page_size } => with_query_param(uri,offset_param,"0")
+                .and_then(|u| with_query_param(&u,limit_param,&page_size.to_string())),
+            _ => Some(uri.clone())
+        }
+    }
+    // Returns the items array of a page
+    pub fn items_mut<'a>(&self, page: &'a mut Value) -> Option<&'a mut Vec<Value>>{
+        page.pointer_mut(&self.items).and_then(|v| v.as_array_mut())
+    }
+    // The next page must be on the same scheme and authority so that credentials aren't sent elsewhere
+    pub fn next_uri(&self, current: &hyper::Uri, page: &Value, headers: &hyper::HeaderMap, page_items: usize) -> Option<hyper::Uri>{
+        let next = match &self.mode{
+            PageMode::Cursor{ field, param } => {
+                let cursor = match page.pointer(field){
+                    Some(Value::String(s)) if !s.is_empty() => s.clone(),
+                    Some(Value::Number(n)) => n.to_string(),
+                    _ => return None
+                };
+                with_query_param(current,param,&cursor)?
+            },
+            PageMode::Link => {
+                let target = next_link(headers)?;
+                match resolve_link(current,&target){
+                    Some(uri) => uri,
+                    None => {
+                        eprintln!("Next page link '{target}' could not be resolved, pagination stopped");
+                        return None
+                    }
+                }
+            },
+            PageMode::Offset{ offset_param, page_size, .. } => {
+                if (page_items as u64) < *page_size{
+                    return None
+                }
+                let offset = QueryParams::from_str(current.query().unwrap_or(""))
+                    .map.get(offset_param)
+                    .and_then(|v| v.as_ref().and_then(|v| v.parse::<u64>().ok()))
+                    .unwrap_or(0);
+                with_query_param(current,offset_param,&(offset + page_size).to_string())?
+            }
+        };
+        if next.scheme() != current.scheme() || next.authority() != current.authority(){
+            eprintln!("Next page '{next}' is on a different host, pagination stopped");
+            return None
+        }
+        match &next == current{
+            true => None,
+            false => Some(next)
+        }
+    }
+}
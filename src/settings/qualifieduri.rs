@@ -2,11 +2,50 @@
 use crate::settings::ServerConfigError;
 use std::collections::HashMap;
 use http::uri::Builder;
+use regex_lite::Regex;
+use percent_encoding::{AsciiSet,NON_ALPHANUMERIC,percent_decode_str,utf8_percent_encode};
+
+// Everything except unreserved characters is encoded, so a value can't add path segments or change the authority
+static PATH_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+#[derive(Debug)]
+enum TemplatePart{
+    Literal(String),
+    Param(usize)
+}
+
+#[derive(Debug)]
+pub struct PathParam{
+    name: String,
+    pattern: Option<Regex>
+}
+
+impl PathParam{
+    // Values are percent-decoded before validation and encoded again when filled into the path
+    fn encode(&self, raw: &str) -> Result<String,ServerConfigError>{
+        let value = match percent_decode_str(raw).decode_utf8(){
+            Ok(v) => v,
+            Err(_) => return Err(ServerConfigError::InvalidValue)
+        };
+        if value.is_empty() || value == "." || value == ".."{
+            return Err(ServerConfigError::InvalidValue)
+        }
+        if let Some(pattern) = &self.pattern{
+            if !pattern.is_match(&value){
+                eprintln!("Path parameter '{}' doesn't match the required pattern",self.name);
+                return Err(ServerConfigError::InvalidValue)
+            }
+        }
+        Ok(utf8_percent_encode(&value,PATH_VALUE).to_string())
+    }
+}
 
 #[derive(Debug)]
 pub struct QualifiedUri{
     inner: hyper::Uri,
-    query: QueryParams
+    query: QueryParams,
+    template: Vec<TemplatePart>,
+    params: Vec<PathParam>
 }
 #[derive(Debug)]
 pub struct QueryParams{
@@ -24,6 +63,14 @@ impl QueryParams{
         }
         QueryParams{map:map}
     }
+    pub fn decoded(&self) -> HashMap<String,String>{
+        self.map.iter()
+            .filter_map(|(k,v)| {
+                let value = v.as_deref().unwrap_or("").replace('+'," ");
+                percent_decode_str(&value).decode_utf8().ok().map(|d| (k.clone(),d.into_owned()))
+            })
+            .collect()
+    }
     pub fn stringify(&self) -> Option<String>{
         if self.map.len() == 0{
             return None
@@ -49,8 +96,64 @@ impl QueryParams{
     }
 }
 
+// Splits the path into literal parts and {param} placeholders
+fn parse_template(path: &str) -> Result<(Vec<TemplatePart>,Vec<PathParam>),ServerConfigError>{
+    let mut parts = vec![];
+    let mut params : Vec<PathParam> = vec![];
+    let mut rest = path;
+    while let Some(start) = rest.find('{'){
+        let end = match rest[start..].find('}'){
+            Some(e) => start + e,
+            None => return Err(ServerConfigError::InvalidURI)
+        };
+        let name = &rest[start + 1..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || params.iter().any(|p| p.name == name){
+            return Err(ServerConfigError::InvalidURI)
+        }
+        if start > 0{
+            parts.push(TemplatePart::Literal(rest[..start].to_string()));
+        }
+        parts.push(TemplatePart::Param(params.len()));
+        params.push(PathParam{ name: name.to_string(), pattern: None });
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}'){
+        return Err(ServerConfigError::InvalidURI)
+    }
+    if !rest.is_empty(){
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    Ok((parts,params))
+}
+
 impl QualifiedUri{
     pub fn try_build(input: String, disallowed_port: u16) -> Result<QualifiedUri,ServerConfigError>{
+        // Placeholders are only allowed in the path, never in the scheme, host or query
+        let path_start = match input.find("://"){
+            Some(i) => input[i + 3..].find('/').map(|p| p + i + 3).unwrap_or(input.len()),
+            None => return Err(ServerConfigError::InvalidURI)
+        };
+        let path_end = input.find('?').unwrap_or(input.len()).max(path_start);
+        if input[..path_start].contains('{') || input[path_end..].contains('{'){
+            println!("Invalid uri: {}",input);
+            return Err(ServerConfigError::InvalidURI)
+        }
+        let (template,params) = parse_template(&input[path_start..path_end])?;
+        // The placeholders are substituted only to validate the rest of the uri
+        let input = match params.is_empty(){
+            true => input,
+            false => {
+                let mut sanitized = input[..path_start].to_string();
+                for part in template.iter(){
+                    match part{
+                        TemplatePart::Literal(l) => sanitized.push_str(l),
+                        TemplatePart::Param(_) => sanitized.push('_')
+                    }
+                }
+                sanitized.push_str(&input[path_end..]);
+                sanitized
+            }
+        };
         let uri : hyper::Uri = match input.parse(){
             Ok(s) => s,
             Err(_) => { println!("Invalid uri: {}",input); return Err(ServerConfigError::InvalidURI) }
@@ -80,12 +183,85 @@ impl QualifiedUri{
         match constructed_uri{
             Ok(c) => Ok(QualifiedUri{
                 inner: c,
-                query: QueryParams::from_str(uri.query().unwrap_or(""))
+                query: QueryParams::from_str(uri.query().unwrap_or("")),
+                template,
+                params
             }),
             Err(_) => Err(ServerConfigError::InvalidURI)
         }
         
     }
+    // Sets the regex patterns of path parameters, patterns must match the whole value
+    pub fn with_patterns(mut self, patterns: &HashMap<String,String>) -> Result<Self,ServerConfigError>{
+        for (name,pattern) in patterns.iter(){
+            let param = match self.params.iter_mut().find(|p| &p.name == name){
+                Some(p) => p,
+                None => {
+                    eprintln!("Path parameter '{name}' is not used in url");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            };
+            match Regex::new(&format!("^(?:{pattern})$")){
+                Ok(re) => param.pattern = Some(re),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            }
+        }
+        Ok(self)
+    }
+    // Decoded path parameter values by name
+    pub fn named_params(&self, values: &[String]) -> HashMap<String,String>{
+        self.params.iter().zip(values.iter())
+            .filter_map(|(p,v)| percent_decode_str(v).decode_utf8().ok().map(|d| (p.name.clone(),d.into_owned())))
+            .collect()
+    }
+    pub fn param_count(&self) -> usize{
+        self.params.len()
+    }
+    pub fn is_templated(&self) -> bool{
+        !self.params.is_empty()
+    }
+    // Fills the placeholders with the given values, scheme and authority are always taken from configuration
+    pub fn filled(&self, values: &[String], params: Option<QueryParams>) -> Result<hyper::Uri,ServerConfigError>{
+        if !self.is_templated(){
+            return match params{
+                Some(p) => self.composed(p),
+                None => Ok(self.uri())
+            }
+        }
+        if values.len() != self.params.len(){
+            return Err(ServerConfigError::InvalidValue)
+        }
+        let mut path = String::new();
+        for part in self.template.iter(){
+            match part{
+                TemplatePart::Literal(l) => path.push_str(l),
+                TemplatePart::Param(i) => path.push_str(&self.params[*i].encode(&values[*i])?)
+            }
+        }
+        if path.is_empty(){
+            path.push('/');
+        }
+        let query = match params{
+            Some(p) => Some(self.query.extend_with(p)),
+            None => self.query.stringify()
+        };
+        if let Some(q) = query{
+            path.push('?');
+            path.push_str(&q);
+        }
+        let scheme = self.inner.scheme_str().unwrap_or("https");
+        let authority = match self.inner.authority(){
+            Some(a) => a.as_str(),
+            None => return Err(ServerConfigError::InvalidURI)
+        };
+        match Builder::new().scheme(scheme).authority(authority).path_and_query(path).build(){
+            Ok(uri) => Ok(uri),
+            Err(_) => Err(ServerConfigError::InvalidURI)
+        }
+    }
     pub fn uri(&self) -> hyper::Uri{
         if let Some(s) = self.query.stringify(){
             let mut constructed = self.inner.to_string();
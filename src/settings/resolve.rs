@@ -0,0 +1,29 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+// Fixed addresses for upstream host names, TLS and the Host header still use the name itself
+#[derive(Debug,Clone,Default)]
+pub struct ResolveOverrides{
+    hosts: HashMap<String,SocketAddr>
+}
+
+impl ResolveOverrides{
+    pub fn try_parse_hosts(table: &config::Map<String, config::Value>) -> Self{
+        let mut hosts = HashMap::new();
+        for (host,val) in table.iter(){
+            match val.clone().into_string().map(|s| s.parse::<SocketAddr>()){
+                Ok(Ok(address)) => {
+                    hosts.insert(host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase(),address);
+                },
+                Ok(Err(e)) => eprintln!("Resolve override for '{host}' is ignored, expected IP:port: {e}"),
+                Err(e) => eprintln!("Resolve override for '{host}' is ignored: {e}")
+            }
+        }
+        ResolveOverrides{ hosts }
+    }
+    pub fn lookup(&self, destination: &hyper::Uri) -> Option<SocketAddr>{
+        let host = destination.host()?.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        self.hosts.get(&host).copied()
+    }
+}
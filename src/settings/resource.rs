@@ -1,19 +1,80 @@
 #![deny(warnings)]
 use std::collections::{HashMap,HashSet};
-use crate::settings::{ServerConfigError,commandapi::RequestCommand};
+use crate::settings::{ServerConfigError,commandapi::RequestCommand,parse_config_string_table};
 use crate::models::{RemoteResultType,RemoteData};
 use crate::schemers::{schemaloader::SchemaTree};
 use std::path::PathBuf;
 use super::qualifieduri::{QueryParams,QualifiedUri};
 use super::credentials::{ResourceCredentials};
-use super::header::{Header,HeaderSet,ParseMode};
-use crate::httpsconnector::{RequestOptions,ConnectionError};
+use super::oauth::OAuth2Credentials;
+use super::login::{SessionAuth,SharedLogin};
+use super::cookiejar::{CookieJar,CookieJars};
+use crate::support::secrets::SecretKey;
+use super::retry::{ResourceTimeouts,RetryPolicy};
+use super::breaker::CircuitBreaker;
+use crate::support::singleflight::SingleFlight;
+use super::limits::{UpstreamLimiter,HostLimits};
+use super::tls::{TlsOptions,HostTls};
+use super::bodytemplate::{BodyTemplate,TemplateContext};
+use super::transform::Transform;
+use super::pagination::Pagination;
+use std::sync::Arc;
+use super::header::{Header,HeaderSet,ParseMode,ForwardHeader,parse_header_names};
+use crate::httpsconnector::{RequestOptions,ConnectionError,ConnectorProfile,ClientId,ClientPool,HttpClient,UpstreamData};
+use crate::cache::{CacheEntry,DiskCache};
+use std::sync::RwLock;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum ResourceMethod{
     Get,
-    Post
+    Post,
+    Put,
+    Patch,
+    Delete
 }
+
+impl ResourceMethod{
+    pub fn from_str(input: &str) -> Option<Self>{
+        match input{
+            "GET" | "get" => Some(ResourceMethod::Get),
+            "POST" | "post" => Some(ResourceMethod::Post),
+            "PUT" | "put" => Some(ResourceMethod::Put),
+            "PATCH" | "patch" => Some(ResourceMethod::Patch),
+            "DELETE" | "delete" => Some(ResourceMethod::Delete),
+            _ => None
+        }
+    }
+    pub fn as_method(&self) -> hyper::Method{
+        match self{
+            ResourceMethod::Get => hyper::Method::GET,
+            ResourceMethod::Post => hyper::Method::POST,
+            ResourceMethod::Put => hyper::Method::PUT,
+            ResourceMethod::Patch => hyper::Method::PATCH,
+            ResourceMethod::Delete => hyper::Method::DELETE
+        }
+    }
+    // Whether the incoming request body is passed on to the upstream
+    pub fn has_body(&self) -> bool{
+        match self{
+            ResourceMethod::Post | ResourceMethod::Put | ResourceMethod::Patch => true,
+            ResourceMethod::Get | ResourceMethod::Delete => false
+        }
+    }
+    // Only GET is answered from the cache, other requests depend on their body or modify data
+    pub fn is_cacheable(&self) -> bool{
+        match self{
+            ResourceMethod::Get => true,
+            _ => false
+        }
+    }
+}
+#[derive(Debug,PartialEq)]
+pub enum ResourceFallback{
+    None,
+    LastGood
+}
+
 #[derive(Debug)]
 pub struct ResourceStore{
     inner: HashMap<String,RemoteResource>
@@ -27,18 +88,76 @@ impl ResourceStore{
     pub fn inner(&self) -> &HashMap<String,RemoteResource>{
         &self.inner
     }
-    pub fn try_parse(table : &HashMap<String, config::Value>, schema_source: &Option<SchemaTree>, port_number : u16) -> Result<ResourceStore,ServerConfigError>{
+    // The connector settings are compared once here instead of on every request
+    pub fn assign_clients(&mut self, pool: &mut ClientPool){
+        for resource in self.inner.values_mut(){
+            resource.client = pool.register(resource.connector_profile());
+        }
+    }
+    pub fn try_parse(table : &HashMap<String, config::Value>, schema_source: &Option<SchemaTree>, port_number : u16, host_limits: &HostLimits, host_tls: &HostTls, cookie_jars: &CookieJars, secret_key: &Arc<SecretKey>) -> Result<ResourceStore,ServerConfigError>{
         let mut map = HashMap::new();
+        // Resources with the same session name share a jar, sessions without settings get an in-memory one
+        let mut jars = cookie_jars.clone();
         for (key,val) in table.iter(){
-            if let Ok(remote) = RemoteResource::try_from_config(&val,port_number,schema_source){
+            if let Ok(mut remote) = RemoteResource::try_from_config(key,&val,port_number,schema_source,host_limits,host_tls,secret_key){
+                if let Some(session) = &remote.session{
+                    remote.cookie_jar = Some(jars.entry(session.clone()).or_insert_with(|| Arc::new(CookieJar::new(None))).clone());
+                }
                 map.insert(key.clone(),remote);
             };
                 
         }
+        // Login resources must exist and can't depend on another login themselves
+        let logins : HashMap<String,bool> = map.iter().map(|(k,v)| (k.clone(),v.session_auth.is_none())).collect();
+        map.retain(|name,remote| match &remote.session_auth{
+            Some(auth) if logins.get(&auth.login) != Some(&true) => {
+                eprintln!("{name}: auth_from resource '{}' is missing or requires a login itself",auth.login);
+                false
+            },
+            _ => true
+        });
+        // Resources logging in through the same resource share its session
+        let mut shared_logins : HashMap<String,SharedLogin> = HashMap::new();
+        for remote in map.values_mut(){
+            if let Some(auth) = remote.session_auth.as_mut(){
+                let shared = shared_logins.entry(auth.login.clone()).or_insert_with(|| Arc::new(tokio::sync::Mutex::new(None))).clone();
+                auth.share(shared);
+            }
+        }
         Ok(ResourceStore{
             inner: map
         })
     }
+    pub fn health(&self) -> serde_json::Value{
+        let mut map = serde_json::Map::new();
+        for (name,resource) in self.inner.iter(){
+            let circuit = match &resource.breaker{
+                Some(breaker) => breaker.status(),
+                None => serde_json::Value::Null
+            };
+            map.insert(name.clone(),serde_json::json!({
+                "cached": resource.get_cached().is_some(),
+                "circuit": circuit
+            }));
+        }
+        serde_json::Value::Object(map)
+    }
+    pub fn warm_caches(&self, disk_cache: &DiskCache) -> usize{
+        let mut count = 0;
+        for (name,resource) in self.inner.iter(){
+            if resource.no_cache{
+                continue
+            }
+            match disk_cache.load(name){
+                Ok(entry) => if resource.warm_cache(entry){
+                    count += 1
+                },
+                Err(crate::cache::CacheError::NotFound) => (),
+                Err(e) => eprintln!("{name}: {e}")
+            }
+        }
+        count
+    }
 }
 
 pub struct RequestCredentials{
@@ -63,6 +182,14 @@ impl WriteTarget{
         file.write_all(stream.data_bytes()).await?;
         Ok(())
     }
+    pub async fn read_file(&self) -> Result<(Vec<u8>,Duration),std::io::Error>{
+        let bytes = tokio::fs::read(&self.path).await?;
+        let age = tokio::fs::metadata(&self.path).await?
+            .modified()?
+            .elapsed()
+            .unwrap_or(Duration::ZERO);
+        Ok((bytes,age))
+    }
 }
 
 #[allow(unused)]
@@ -70,6 +197,7 @@ pub trait TryParseTypedValue{
     fn try_parse_string(&self,key : &str) -> Result<String,ServerConfigError>;
     fn try_parse_u64(&self, key: &str) -> Result<u64,ServerConfigError>;
     fn try_parse_u16(&self, key: &str) -> Result<u16,ServerConfigError>;
+    fn try_parse_f64(&self, key: &str) -> Result<f64,ServerConfigError>;
     fn try_parse_bool(&self, key: &str) -> Result<bool,ServerConfigError>;
 }
 
@@ -102,6 +230,15 @@ impl TryParseTypedValue for config::Map<String, config::Value>{
             None => Err(ServerConfigError::MissingKey)
         }
     }
+    fn try_parse_f64(&self, key: &str) -> Result<f64,ServerConfigError>{
+        match self.get(key){
+            Some(value) => match value.clone().into_float(){
+                Ok(k) => Ok(k),
+                Err(_) => Err(ServerConfigError::InvalidValue)
+            },
+            None => Err(ServerConfigError::MissingKey)
+        }
+    }
     fn try_parse_u16(&self, key: &str) -> Result<u16,ServerConfigError>{
         match self.get(key){
             Some(value) => match value.clone().into_uint(){
@@ -121,102 +258,254 @@ impl TryParseTypedValue for config::Map<String, config::Value>{
 
 #[derive(Debug)]
 pub struct RemoteResource{
+    pub name: String,
     pub uri: QualifiedUri,
     credentials: Option<ResourceCredentials>,
+    secret_key: Arc<SecretKey>,
+    pub oauth: Option<OAuth2Credentials>,
+    pub session_auth: Option<SessionAuth>,
+    pub session: Option<String>,
+    pub cookie_jar: Option<Arc<CookieJar>>,
     pub target: Option<WriteTarget>,
     pub model: crate::models::RemoteResultType,
-    cache: std::sync::OnceLock<RemoteData>,
+    cache: RwLock<Option<CacheEntry>>,
     pub schema: Option<String>,
+    pub request_schema: Option<String>,
+    pub max_body_size: Option<u64>,
+    pub body_template: Option<BodyTemplate>,
+    pub transform: Option<Transform>,
+    pub pagination: Option<Pagination>,
     pub no_cache: bool,
+    pub cache_ttl: Option<Duration>,
     pub forward_queries: Option<HashSet<String>>,
     pub method: ResourceMethod,
-    pub request_headers: HeaderSet
+    pub request_headers: HeaderSet,
+    pub forward_headers: Vec<ForwardHeader>,
+    pub response_headers: Vec<http::HeaderName>,
+    pub fallback: ResourceFallback,
+    pub timeouts: ResourceTimeouts,
+    pub tls: Option<TlsOptions>,
+    pub client: ClientId,
+    pub retry: RetryPolicy,
+    pub breaker: Option<CircuitBreaker>,
+    pub inflight: SingleFlight<Result<UpstreamData,ConnectionError>>,
+    pub limiter: Option<Arc<UpstreamLimiter>>
 }
 
 
 #[allow(unused)]
 impl RemoteResource{
-    pub fn compose_uri(&self, query: &str) -> Result<hyper::Uri,ServerConfigError>{
+    pub fn compose_uri(&self, query: &str, path_params: &[String]) -> Result<hyper::Uri,ServerConfigError>{
         let mut params = QueryParams::from_str(query);
         match &self.forward_queries{
             Some(fq) => {
                 params.map.retain(|x,_| fq.contains(x));
-                self.uri.composed(params)
+                self.uri.filled(path_params,Some(params))
             },
-            None => Ok(self.uri.uri())
+            None => self.uri.filled(path_params,None)
+        }
+    }
+    // Templated resources share a single cache slot, so entries are only valid for the same path
+    pub fn entry_matches(&self, entry_uri: &str, path_params: &[String]) -> bool{
+        if !self.uri.is_templated(){
+            return true
+        }
+        match (self.uri.filled(path_params,None), entry_uri.parse::<hyper::Uri>()){
+            (Ok(own),Ok(cached)) => own.path() == cached.path(),
+            (_,_) => false
         }
     }
-    pub fn build_request<'a>(&'a self,user_agent: &'a str, request_query: Option<&str>, body: Option<bytes::Bytes>) -> Result<RequestOptions<'a>,ConnectionError>{
-        let credentials = match self.request_credentials(crate::OBFUSCATION_KEY){
+    pub fn connector_profile(&self) -> ConnectorProfile{
+        ConnectorProfile::new(&self.timeouts,self.tls.as_ref())
+    }
+    pub fn build_request<'a>(&'a self,client: &'a HttpClient,user_agent: &'a str, incoming_headers: Option<&hyper::HeaderMap>, path_params: &[String], request_query: Option<&str>, body: Option<bytes::Bytes>) -> Result<RequestOptions<'a>,ConnectionError>{
+        let credentials = match self.request_credentials(){
             Some(res) => match res{
                 Ok(dec) => Some(dec),
                 Err(_) => return Err(ConnectionError::InvalidRequest)
             },
             None => None
         };
-        let uri = match (&self.forward_queries, request_query){
-            (Some(_), Some(query)) => match self.compose_uri(query){
-                Ok(built) => built,
-                Err(_) => return Err(ConnectionError::InvalidRequest)
+        let composed = match (&self.forward_queries, request_query){
+            (Some(_), Some(query)) => self.compose_uri(query,path_params),
+            (_,_) => self.uri.filled(path_params,None)
+        };
+        let uri = match composed{
+            Ok(built) => built,
+            Err(_) => return Err(ConnectionError::InvalidRequest)
+        };
+        let body = match (&self.body_template, body){
+            (Some(template), Some(incoming)) => {
+                let query = match (&self.forward_queries, request_query){
+                    (Some(fq), Some(q)) => {
+                        let mut params = QueryParams::from_str(q);
+                        params.map.retain(|x,_| fq.contains(x));
+                        params.decoded()
+                    },
+                    (_,_) => HashMap::new()
+                };
+                let context = TemplateContext{ body: &incoming, query, path: self.uri.named_params(path_params) };
+                Some(bytes::Bytes::from(template.render(&context)?))
             },
-            (_,_) => self.uri.uri().into(),
+            (_, body) => body
+        };
+        let content_type = self.body_template.as_ref().map(|t| t.format.content_type());
+        let forwarded_headers = match incoming_headers{
+            Some(incoming) => self.forward_headers.iter().filter_map(|fh| fh.resolve(incoming)).collect(),
+            None => vec![]
         };
         //println!("{:?}",&self.request_headers);
         Ok(RequestOptions{
+            forwarded_headers,
             uri,
             credentials,
             user_agent,
             body,
+            content_type,
             method: &self.method,
-            request_headers: &self.request_headers
+            request_headers: &self.request_headers,
+            timeouts: &self.timeouts,
+            retry: &self.retry,
+            client,
+            limiter: self.limiter.as_deref(),
+            pagination: self.pagination.as_ref(),
+            oauth: self.oauth.as_ref(),
+            session: None,
+            cookie_jar: self.cookie_jar.as_deref()
         })
     }
     pub fn request_headers(&self) -> &Vec<Header>{
         self.request_headers.headers()
     }
-    pub fn derive_key(&self, key: &str) -> Result<String,ServerConfigError>{
+    pub fn derive_key(&self, key: &SecretKey) -> Result<String,ServerConfigError>{
         match &self.credentials {
             Some(cred) => cred.derive_key(key),
             None => Err(ServerConfigError::NotAvailable)
         }   
     }
-    pub fn request_credentials(&self, key: &str) -> Option<Result<RequestCredentials,ServerConfigError>>{
+    // Derives the key of encrypted credentials before build_request decrypts them
+    pub async fn prepare_credentials(&self){
+        if let Some(c) = &self.credentials{
+            c.prepare(&self.secret_key).await
+        }
+    }
+    pub fn request_credentials(&self) -> Option<Result<RequestCredentials,ServerConfigError>>{
         match &self.credentials{
-            Some(c) => match c.derive_key(key){
+            Some(c) => match c.derive_key(&self.secret_key){
                 Ok(s) => Some(Ok(RequestCredentials{ key: c.header().to_string(), value: s})),
                 Err(_) => Some(Err(ServerConfigError::DecodeError))
             },
             None => None
         }
     }
-    pub fn get_cached(&self) -> Option<&RemoteData>{
-        self.cache.get()
+    pub fn get_cached(&self) -> Option<RemoteData>{
+        match self.cache.read(){
+            Ok(guard) => match guard.as_ref(){
+                Some(entry) if entry.is_fresh(self.cache_ttl) => Some(entry.data.clone()),
+                _ => None
+            },
+            Err(_) => None
+        }
+    }
+    // Responses that depend on forwarded client headers are never shared between clients
+    pub fn shares_responses(&self) -> bool{
+        self.forward_headers.is_empty()
+    }
+    // The entry is only used for the exact request it was stored for, including the query
+    pub fn get_cached_response(&self, key: &str) -> Option<UpstreamData>{
+        match self.cache.read(){
+            Ok(guard) => match guard.as_ref(){
+                Some(entry) if entry.is_fresh(self.cache_ttl) && entry.uri == key => Some(UpstreamData{
+                    data: entry.data.clone(),
+                    headers: entry.headers.clone()
+                }),
+                _ => None
+            },
+            Err(_) => None
+        }
+    }
+    // Picks the allow-listed headers from upstream response
+    pub fn select_response_headers(&self, upstream: &hyper::HeaderMap) -> hyper::HeaderMap{
+        let mut headers = hyper::HeaderMap::new();
+        for name in self.response_headers.iter(){
+            for value in upstream.get_all(name).iter(){
+                headers.append(name.clone(),value.clone());
+            }
+        }
+        headers
     }
-    pub fn cache_result(&self,data: RemoteData) -> Result<RemoteData,ServerConfigError>{
+    // Returns the cached data and its age even if it has already expired
+    pub fn get_stale(&self, path_params: &[String]) -> Option<(RemoteData,Duration)>{
+        match self.cache.read(){
+            Ok(guard) => guard.as_ref()
+                .filter(|entry| self.entry_matches(&entry.uri,path_params))
+                .map(|entry| (entry.data.clone(),entry.age())),
+            Err(_) => None
+        }
+    }
+    pub fn cache_result(&self,entry: CacheEntry) -> Result<RemoteData,ServerConfigError>{
         if self.no_cache {
             return Err(ServerConfigError::NotAvailable)
         }
-        match self.cache.set(data.clone()){
-            Ok(_) => Ok(data),
+        match self.cache.write(){
+            Ok(mut guard) => {
+                let data = entry.data.clone();
+                *guard = Some(entry);
+                Ok(data)
+            },
             Err(e) => {
                 eprintln!("{:?}",e);
                 Err(ServerConfigError::NotAvailable)
             }
         }
     }
-    fn try_from_config(conf: &config::Value, disallowed_port: u16, tree: &Option<SchemaTree>) -> Result<RemoteResource,ServerConfigError>{
-        try_into_remote(conf,disallowed_port,tree)
+    // Fills the cache from a persisted entry if it still belongs to this resource and hasn't expired
+    pub fn warm_cache(&self, entry: CacheEntry) -> bool{
+        let source : hyper::Uri = match entry.uri.parse(){
+            Ok(uri) => uri,
+            Err(_) => return false
+        };
+        let own = self.uri.uri();
+        if source.authority() != own.authority() || source.path() != own.path() || !entry.is_fresh(self.cache_ttl){
+            return false
+        }
+        self.cache_result(entry).is_ok()
+    }
+    fn try_from_config(name: &str, conf: &config::Value, disallowed_port: u16, tree: &Option<SchemaTree>, host_limits: &HostLimits, host_tls: &HostTls, secret_key: &Arc<SecretKey>) -> Result<RemoteResource,ServerConfigError>{
+        try_into_remote(name,conf,disallowed_port,tree,host_limits,host_tls,secret_key)
     }
 }
 
-fn try_into_remote(conf: &config::Value,disallowed_port: u16, tree: &Option<SchemaTree>) -> Result<RemoteResource,ServerConfigError>{
+fn is_oauth2(credentials: &config::Value) -> bool{
+    match credentials.clone().into_table(){
+        Ok(table) => table.try_parse_string("mode").is_ok_and(|m| m == "oauth2"),
+        Err(_) => false
+    }
+}
+
+fn try_into_remote(name: &str, conf: &config::Value,disallowed_port: u16, tree: &Option<SchemaTree>, host_limits: &HostLimits, host_tls: &HostTls, secret_key: &Arc<SecretKey>) -> Result<RemoteResource,ServerConfigError>{
     match conf.clone().into_table(){
         Ok(table) => match table.try_parse_string("url"){
             Ok(url_string) => {
-                let uri_conversion = QualifiedUri::try_build(url_string,disallowed_port);
+                let uri_conversion = QualifiedUri::try_build(url_string,disallowed_port)
+                    .and_then(|uri| match parse_config_string_table(&table,"path_params"){
+                        Some(patterns) => uri.with_patterns(&patterns),
+                        None => Ok(uri)
+                    });
                 if uri_conversion.is_ok(){
 
+                    let oauth = match table.get("credentials"){
+                        Some(cred) if is_oauth2(cred) => match OAuth2Credentials::try_parse(cred,disallowed_port,secret_key.clone()){
+                            Ok(o) => Some(o),
+                            Err(e) => {
+                                eprintln!("Invalid oauth2 credentials for {name}: {e}");
+                                return Err(e)
+                            }
+                        },
+                        _ => None
+                    };
                     let creds = match table.get("credentials"){
+                        Some(_) if oauth.is_some() => None,
                         Some(cred) => match ResourceCredentials::try_parse(&cred){
                             Ok(cred) => Some(cred),
                             Err(e) => {
@@ -235,10 +524,9 @@ fn try_into_remote(conf: &config::Value,disallowed_port: u16, tree: &Option<Sche
                         Err(_) => None
                     };
                     let request_method = match table.try_parse_string("request_method"){
-                        Ok(k) => match k.as_str(){
-                            "POST" | "post" => ResourceMethod::Post,
-                            "GET" | "get" => ResourceMethod::Get,
-                            _ => return Err(ServerConfigError::InvalidValue)
+                        Ok(k) => match ResourceMethod::from_str(k.as_str()){
+                            Some(method) => method,
+                            None => return Err(ServerConfigError::InvalidValue)
                         },
                         Err(_) => ResourceMethod::Get
                     };
@@ -256,6 +544,55 @@ fn try_into_remote(conf: &config::Value,disallowed_port: u16, tree: &Option<Sche
                         }
                         None => false
                     };
+                    let response_headers = match table.get("response_headers"){
+                        Some(rh) => match parse_header_names(rh){
+                            Ok(list) => list,
+                            Err(e) => {
+                                eprintln!("{e}");
+                                return Err(ServerConfigError::InvalidValue)
+                            }
+                        },
+                        None => vec![]
+                    };
+                    let fallback = match table.try_parse_string("fallback"){
+                        Ok(k) => match k.as_str(){
+                            "last_good" => ResourceFallback::LastGood,
+                            "none" => ResourceFallback::None,
+                            _ => return Err(ServerConfigError::InvalidValue)
+                        },
+                        Err(_) => ResourceFallback::None
+                    };
+                    let timeouts = match table.get("timeouts"){
+                        Some(t) => ResourceTimeouts::try_parse(t)?,
+                        None => ResourceTimeouts::default()
+                    };
+                    let retry = match table.get("retry"){
+                        Some(r) => RetryPolicy::try_parse(r)?,
+                        None => RetryPolicy::default()
+                    };
+                    let breaker = match table.get("circuit_breaker"){
+                        Some(b) => Some(CircuitBreaker::try_parse(b)?),
+                        None => None
+                    };
+                    // Resource specific limits take precedence over limits of the upstream host
+                    let limiter = match table.get("limits"){
+                        Some(l) => Some(Arc::new(UpstreamLimiter::try_parse(l)?)),
+                        None => uri_conversion.as_ref().ok()
+                            .and_then(|u| u.uri().host().map(|h| h.to_lowercase()))
+                            .and_then(|host| host_limits.get(&host).cloned())
+                    };
+                    // Likewise resource specific TLS settings replace those of the upstream host
+                    let tls = match table.get("tls"){
+                        Some(t) => Some(TlsOptions::try_parse(t,name)?),
+                        None => uri_conversion.as_ref().ok()
+                            .and_then(|u| u.uri().host().map(|h| h.to_lowercase()))
+                            .and_then(|host| host_tls.get(&host).cloned())
+                    };
+                    let cache_ttl = match table.try_parse_u64("cache_ttl"){
+                        Ok(secs) => Some(Duration::from_secs(secs)),
+                        Err(ServerConfigError::MissingKey) => None,
+                        Err(e) => return Err(e)
+                    };
                     let forward_queries = match table.get("forward_queries"){
                         Some(va) => match va.clone().into_array(){
                             Ok(list) => {
@@ -291,6 +628,66 @@ fn try_into_remote(conf: &config::Value,disallowed_port: u16, tree: &Option<Sche
                     if !data_model.supports_schema() && schema.is_some(){
                         return Err(ServerConfigError::UnsupportedSchema)
                     }
+                    let transform = match table.get("transform"){
+                        Some(_) if !data_model.supports_schema() => {
+                            eprintln!("transform is only supported with model 'json'");
+                            return Err(ServerConfigError::InvalidValue)
+                        },
+                        Some(t) => Some(Transform::try_parse(t)?),
+                        None => None
+                    };
+                    let pagination = match table.get("pagination"){
+                        Some(p) if matches!(request_method,ResourceMethod::Get) => Some(Pagination::try_parse(p)?),
+                        Some(_) => {
+                            eprintln!("pagination is only supported with GET requests");
+                            return Err(ServerConfigError::InvalidValue)
+                        },
+                        None => None
+                    };
+                    let session_auth = match (table.try_parse_string("auth_from"),table.get("auth")){
+                        (Ok(login),Some(_)) if login == name => {
+                            eprintln!("{name} can't log in through itself");
+                            return Err(ServerConfigError::InvalidValue)
+                        },
+                        (Ok(_),Some(_)) if oauth.is_some() => {
+                            eprintln!("auth_from can't be combined with oauth2 credentials");
+                            return Err(ServerConfigError::InvalidValue)
+                        },
+                        (Ok(login),Some(rule)) => Some(SessionAuth::try_parse(login,rule)?),
+                        (Ok(_),None) => return Err(ServerConfigError::MissingKey),
+                        (Err(ServerConfigError::MissingKey),_) => None,
+                        (Err(e),_) => return Err(e)
+                    };
+                    let session = match table.try_parse_string("session"){
+                        Ok(s) => Some(s),
+                        Err(ServerConfigError::MissingKey) => None,
+                        Err(e) => return Err(e)
+                    };
+                    let request_schema = match (&tree,table.get("request_schema")){
+                        (Some(onetree),Some(s)) => match s.clone().into_string(){
+                            Ok(t) if onetree.contains_schema(&t) => Some(t),
+                            _ => return Err(ServerConfigError::UnsupportedSchema)
+                        },
+                        (None,Some(_)) => return Err(ServerConfigError::UnsupportedSchema),
+                        (_,None) => None
+                    };
+                    if request_schema.is_some() && !request_method.has_body(){
+                        eprintln!("request_schema requires a method with a request body");
+                        return Err(ServerConfigError::InvalidValue)
+                    }
+                    let body_template = match table.get("body_template"){
+                        Some(t) if request_method.has_body() => Some(BodyTemplate::try_parse(t)?),
+                        Some(_) => {
+                            eprintln!("body_template requires a method with a request body");
+                            return Err(ServerConfigError::InvalidValue)
+                        },
+                        None => None
+                    };
+                    let max_body_size = match table.try_parse_u64("max_body_size"){
+                        Ok(bytes) => Some(bytes),
+                        Err(ServerConfigError::MissingKey) => None,
+                        Err(e) => return Err(e)
+                    };
                     let request_headers = match table.get("headers"){
                         Some(s) => match HeaderSet::parse_literals(&s,ParseMode::Strict){
                             Ok(heads) => heads,
@@ -301,17 +698,65 @@ fn try_into_remote(conf: &config::Value,disallowed_port: u16, tree: &Option<Sche
                         },
                         None => HeaderSet::new()
                     };
+                    // Headers that are always set by the request itself can't be forwarded as well
+                    let mut reserved = vec![http::header::USER_AGENT.to_string()];
+                    if let Some(cred) = &creds{
+                        reserved.push(cred.header().to_string());
+                    }
+                    reserved.extend(request_headers.headers().iter().map(|h| h.name().as_str().to_string()));
+                    if oauth.is_some(){
+                        reserved.push(http::header::AUTHORIZATION.to_string());
+                    }
+                    if let Some(auth) = &session_auth{
+                        reserved.extend([http::header::AUTHORIZATION.to_string(),http::header::COOKIE.to_string(),auth.header().to_string()]);
+                    }
+                    // Resources with a session send the cookies of their jar
+                    if session.is_some(){
+                        reserved.push(http::header::COOKIE.to_string());
+                    }
+                    let forward_headers = match table.get("forward_headers"){
+                        Some(fh) => match ForwardHeader::parse_list(fh,&reserved){
+                            Ok(list) => list,
+                            Err(e) => {
+                                eprintln!("{e}");
+                                return Err(ServerConfigError::InvalidValue)
+                            }
+                        },
+                        None => vec![]
+                    };
                     return Ok(RemoteResource{
+                        name: name.to_string(),
                         uri: uri_conversion.unwrap(),
                         method: request_method,
                         credentials: creds,
+                        secret_key: secret_key.clone(),
+                        oauth,
+                        session_auth,
+                        session,
+                        cookie_jar: None,
                         target: write_target,
                         model: data_model,
-                        cache: std::sync::OnceLock::new(),
+                        cache: RwLock::new(None),
                         no_cache: no_cache,
+                        cache_ttl,
                         schema: schema,
+                        request_schema,
+                        max_body_size,
+                        body_template,
+                        transform,
+                        pagination,
                         forward_queries: forward_queries,
-                        request_headers: request_headers
+                        request_headers: request_headers,
+                        forward_headers,
+                        response_headers,
+                        fallback,
+                        timeouts,
+                        tls,
+                        client: 0,
+                        retry,
+                        breaker,
+                        inflight: SingleFlight::new(),
+                        limiter
                     });
                 }
                 eprintln!("Resource with invalid url is ignored");
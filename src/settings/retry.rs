@@ -0,0 +1,153 @@
+#![deny(warnings)]
+use std::collections::HashSet;
+use std::time::Duration;
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+static DEFAULT_RETRY_STATUS: [u16;4] = [429,502,503,504];
+
+#[derive(Debug,Clone)]
+pub struct ResourceTimeouts{
+    pub connect: Option<Duration>,
+    pub request: Option<Duration>,
+    pub total: Option<Duration>
+}
+
+impl Default for ResourceTimeouts{
+    fn default() -> Self{
+        ResourceTimeouts{
+            connect: Some(Duration::from_secs(10)),
+            request: Some(Duration::from_secs(60)),
+            total: None
+        }
+    }
+}
+
+fn parse_seconds(table: &config::Map<String, config::Value>, key: &str, default: Option<Duration>) -> Result<Option<Duration>,ServerConfigError>{
+    match table.try_parse_u64(key){
+        Ok(0) => Ok(None),
+        Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+        Err(ServerConfigError::MissingKey) => Ok(default),
+        Err(e) => Err(e)
+    }
+}
+
+impl ResourceTimeouts{
+    // Values are in seconds, zero disables the timeout
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let defaults = ResourceTimeouts::default();
+        Ok(ResourceTimeouts{
+            connect: parse_seconds(&table,"connect",defaults.connect)?,
+            request: parse_seconds(&table,"request",defaults.request)?,
+            total: parse_seconds(&table,"total",defaults.total)?
+        })
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct RetryPolicy{
+    pub max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    status_codes: HashSet<u16>,
+    pub non_idempotent: bool
+}
+
+impl Default for RetryPolicy{
+    fn default() -> Self{
+        RetryPolicy{
+            max_attempts: 1,
+            backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            status_codes: HashSet::from(DEFAULT_RETRY_STATUS),
+            non_idempotent: false
+        }
+    }
+}
+
+impl RetryPolicy{
+    pub fn attempts_for(&self, idempotent: bool) -> u32{
+        match idempotent || self.non_idempotent{
+            true => self.max_attempts,
+            false => 1
+        }
+    }
+    pub fn is_retryable_status(&self, status: hyper::StatusCode) -> bool{
+        self.status_codes.contains(&status.as_u16())
+    }
+    // Exponential backoff with jitter, the delay is picked from the upper half of the window
+    pub fn backoff_for(&self, attempt: u32) -> Duration{
+        let exponent = attempt.saturating_sub(1).min(16);
+        let window = self.backoff.saturating_mul(1 << exponent).min(self.max_backoff);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let half = window / 2;
+        half + half.mul_f64((nanos % 1000) as f64 / 1000.0)
+    }
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let defaults = RetryPolicy::default();
+        let max_attempts = match table.try_parse_u64("max_attempts"){
+            Ok(n) if n > 0 && n <= 10 => n as u32,
+            Ok(_) => return Err(ServerConfigError::InvalidValue),
+            Err(ServerConfigError::MissingKey) => 3,
+            Err(e) => return Err(e)
+        };
+        let backoff = match table.try_parse_u64("backoff_ms"){
+            Ok(ms) => Duration::from_millis(ms),
+            Err(ServerConfigError::MissingKey) => defaults.backoff,
+            Err(e) => return Err(e)
+        };
+        let max_backoff = match table.try_parse_u64("max_backoff_ms"){
+            Ok(ms) => Duration::from_millis(ms),
+            Err(ServerConfigError::MissingKey) => defaults.max_backoff,
+            Err(e) => return Err(e)
+        };
+        let status_codes = match table.get("status_codes"){
+            Some(va) => match va.clone().into_array(){
+                Ok(list) => {
+                    let mut set = HashSet::new();
+                    for val in list.into_iter(){
+                        match val.into_uint().ok().and_then(|u| u16::try_from(u).ok()){
+                            Some(code) => set.insert(code),
+                            None => return Err(ServerConfigError::InvalidValue)
+                        };
+                    }
+                    set
+                },
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            },
+            None => defaults.status_codes
+        };
+        let non_idempotent = match table.try_parse_bool("non_idempotent"){
+            Ok(b) => b,
+            Err(ServerConfigError::MissingKey) => false,
+            Err(e) => return Err(e)
+        };
+        Ok(RetryPolicy{
+            max_attempts,
+            backoff,
+            max_backoff,
+            status_codes,
+            non_idempotent
+        })
+    }
+}
@@ -0,0 +1,164 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::path::Path;
+use native_tls::{Certificate,Identity,Protocol,TlsConnector};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+pub type HostTls = HashMap<String,TlsOptions>;
+
+#[derive(Debug,Clone,Copy,Hash,PartialEq,Eq)]
+enum TlsVersion{
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13
+}
+
+impl TlsVersion{
+    fn from_str(input: &str) -> Option<Self>{
+        match input{
+            "1.0" => Some(TlsVersion::Tls10),
+            "1.1" => Some(TlsVersion::Tls11),
+            "1.2" => Some(TlsVersion::Tls12),
+            "1.3" => Some(TlsVersion::Tls13),
+            _ => None
+        }
+    }
+    fn protocol(&self) -> Protocol{
+        match self{
+            TlsVersion::Tls10 => Protocol::Tlsv10,
+            TlsVersion::Tls11 => Protocol::Tlsv11,
+            TlsVersion::Tls12 => Protocol::Tlsv12,
+            TlsVersion::Tls13 => Protocol::Tlsv13
+        }
+    }
+}
+
+// PEM contents are read when the settings are parsed, so a missing file is reported at startup
+#[derive(Debug,Clone,Hash,PartialEq,Eq)]
+pub struct TlsOptions{
+    ca_certificates: Vec<Vec<u8>>,
+    // Client certificate chain and PKCS#8 key for mutual TLS
+    identity: Option<(Vec<u8>,Vec<u8>)>,
+    min_version: Option<TlsVersion>,
+    insecure_skip_verify: bool
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>,ServerConfigError>{
+    match std::fs::read(Path::new(path)){
+        Ok(bytes) => Ok(bytes),
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            Err(ServerConfigError::InvalidValue)
+        }
+    }
+}
+
+// A CA file without a single certificate is most likely the wrong file
+fn read_ca(path: &str) -> Result<Vec<u8>,ServerConfigError>{
+    let pem = read_pem(path)?;
+    match Certificate::stack_from_pem(&pem){
+        Ok(certificates) if !certificates.is_empty() => Ok(pem),
+        _ => {
+            eprintln!("{path} contains no PEM certificates");
+            Err(ServerConfigError::InvalidValue)
+        }
+    }
+}
+
+impl TlsOptions{
+    // ca_file may be a single path or a list of paths
+    pub fn try_parse(input: &config::Value, owner: &str) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let ca_files = match table.get("ca_file"){
+            Some(value) => match value.clone().into_array(){
+                Ok(list) => list.into_iter().map(|v| v.into_string()).collect::<Result<Vec<String>,_>>(),
+                Err(_) => value.clone().into_string().map(|s| vec![s])
+            },
+            None => Ok(vec![])
+        };
+        let ca_certificates = match ca_files{
+            Ok(files) => files.iter().map(|f| read_ca(f)).collect::<Result<Vec<Vec<u8>>,ServerConfigError>>()?,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let identity = match (table.try_parse_string("client_cert"),table.try_parse_string("client_key")){
+            (Ok(cert),Ok(key)) => Some((read_pem(&cert)?,read_pem(&key)?)),
+            (Err(ServerConfigError::MissingKey),Err(ServerConfigError::MissingKey)) => None,
+            (_,_) => {
+                eprintln!("client_cert and client_key must be given together");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let min_version = match table.try_parse_string("min_version"){
+            Ok(v) => match TlsVersion::from_str(&v){
+                Some(version) => Some(version),
+                None => {
+                    eprintln!("Unsupported TLS version: {v}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            },
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        let insecure_skip_verify = match table.try_parse_bool("insecure_skip_verify"){
+            Ok(b) => b,
+            Err(ServerConfigError::MissingKey) => false,
+            Err(e) => return Err(e)
+        };
+        let options = TlsOptions{ ca_certificates, identity, min_version, insecure_skip_verify };
+        // Building the connector once validates the certificates and the key
+        if let Err(e) = options.connector(){
+            eprintln!("TLS settings of '{owner}' are invalid: {e}");
+            return Err(ServerConfigError::InvalidValue)
+        }
+        if insecure_skip_verify{
+            eprintln!("WARNING: TLS certificate verification is DISABLED for '{owner}'. Connections can be intercepted, never use this outside a lab environment!");
+        }
+        Ok(options)
+    }
+    pub fn try_parse_hosts(table: &config::Map<String, config::Value>) -> HostTls{
+        let mut map = HashMap::new();
+        for (host,val) in table.iter(){
+            match TlsOptions::try_parse(val,host){
+                Ok(options) => {
+                    map.insert(host.to_lowercase(),options);
+                },
+                Err(e) => eprintln!("Upstream TLS settings for '{host}' are ignored: {e}")
+            }
+        }
+        map
+    }
+    #[cfg(test)]
+    pub fn has_identity(&self) -> bool{
+        self.identity.is_some()
+    }
+    pub fn connector(&self) -> Result<TlsConnector,native_tls::Error>{
+        let mut builder = TlsConnector::builder();
+        for pem in self.ca_certificates.iter(){
+            for certificate in Certificate::stack_from_pem(pem)?.into_iter(){
+                builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some((cert,key)) = &self.identity{
+            builder.identity(Identity::from_pkcs8(cert,key)?);
+        }
+        if let Some(version) = &self.min_version{
+            builder.min_protocol_version(Some(version.protocol()));
+        }
+        if self.insecure_skip_verify{
+            builder.danger_accept_invalid_certs(true);
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        builder.build()
+    }
+}
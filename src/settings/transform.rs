@@ -0,0 +1,231 @@
+#![deny(warnings)]
+use std::cmp::Ordering;
+use serde_json::{Value,Map};
+use super::resource::TryParseTypedValue;
+use crate::settings::ServerConfigError;
+
+type ConfigMap = config::Map<String, config::Value>;
+
+// Field references are JSON pointers, the leading '/' is optional
+fn pointer(input: &str) -> String{
+    match input.starts_with('/') || input.is_empty(){
+        true => input.to_string(),
+        false => format!("/{input}")
+    }
+}
+
+fn as_tables(input: &config::Value) -> Result<Vec<ConfigMap>,ServerConfigError>{
+    if let Ok(table) = input.clone().into_table(){
+        return Ok(vec![table])
+    }
+    match input.clone().into_array(){
+        Ok(list) => list.into_iter().map(|v| v.into_table().map_err(|_| ServerConfigError::InvalidValue)).collect(),
+        Err(e) => {
+            eprintln!("{e}");
+            Err(ServerConfigError::InvalidValue)
+        }
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering>{
+    match (a,b){
+        (Value::Number(x),Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x),Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x),Value::Bool(y)) => Some(x.cmp(y)),
+        _ => None
+    }
+}
+
+#[derive(Debug)]
+enum FilterOp{
+    Equals,
+    NotEquals,
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+    Contains,
+    Exists
+}
+
+#[derive(Debug)]
+struct Filter{
+    field: String,
+    op: FilterOp,
+    value: Value
+}
+
+impl Filter{
+    fn try_parse(table: &ConfigMap) -> Result<Self,ServerConfigError>{
+        let field = pointer(&table.try_parse_string("field")?);
+        let op = match table.try_parse_string("op"){
+            Ok(op) => match op.as_str(){
+                "eq" => FilterOp::Equals,
+                "ne" => FilterOp::NotEquals,
+                "gt" => FilterOp::GreaterThan,
+                "gte" => FilterOp::GreaterOrEqual,
+                "lt" => FilterOp::LessThan,
+                "lte" => FilterOp::LessOrEqual,
+                "contains" => FilterOp::Contains,
+                "exists" => FilterOp::Exists,
+                _ => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => FilterOp::Equals,
+            Err(e) => return Err(e)
+        };
+        let value = match table.get("value"){
+            Some(v) => match v.clone().try_deserialize::<Value>(){
+                Ok(v) => v,
+                Err(_) => return Err(ServerConfigError::InvalidValue)
+            },
+            None if matches!(op,FilterOp::Exists) => Value::Null,
+            None => return Err(ServerConfigError::MissingKey)
+        };
+        Ok(Filter{ field, op, value })
+    }
+    fn matches(&self, item: &Value) -> bool{
+        let found = match item.pointer(&self.field){
+            Some(v) => v,
+            None => return matches!(self.op,FilterOp::NotEquals)
+        };
+        match self.op{
+            FilterOp::Equals => found == &self.value || compare(found,&self.value) == Some(Ordering::Equal),
+            FilterOp::NotEquals => found != &self.value && compare(found,&self.value) != Some(Ordering::Equal),
+            FilterOp::GreaterThan => compare(found,&self.value) == Some(Ordering::Greater),
+            FilterOp::GreaterOrEqual => matches!(compare(found,&self.value),Some(Ordering::Greater | Ordering::Equal)),
+            FilterOp::LessThan => compare(found,&self.value) == Some(Ordering::Less),
+            FilterOp::LessOrEqual => matches!(compare(found,&self.value),Some(Ordering::Less | Ordering::Equal)),
+            FilterOp::Contains => match (found,&self.value){
+                (Value::String(s),Value::String(part)) => s.contains(part.as_str()),
+                (Value::Array(list),v) => list.contains(v),
+                _ => false
+            },
+            FilterOp::Exists => !found.is_null()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SortKey{
+    field: String,
+    descending: bool
+}
+
+impl SortKey{
+    fn try_parse(table: &ConfigMap) -> Result<Self,ServerConfigError>{
+        let field = pointer(&table.try_parse_string("field")?);
+        let descending = match table.try_parse_string("order"){
+            Ok(order) => match order.as_str(){
+                "asc" => false,
+                "desc" => true,
+                _ => return Err(ServerConfigError::InvalidValue)
+            },
+            Err(ServerConfigError::MissingKey) => false,
+            Err(e) => return Err(e)
+        };
+        Ok(SortKey{ field, descending })
+    }
+}
+
+#[derive(Debug)]
+pub struct Transform{
+    select: Option<String>,
+    filters: Vec<Filter>,
+    sort: Vec<SortKey>,
+    // Output key and pointer to the source value
+    fields: Option<Vec<(String,String)>>
+}
+
+impl Transform{
+    pub fn try_parse(input: &config::Value) -> Result<Self,ServerConfigError>{
+        let table = match input.clone().into_table(){
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("{e}");
+                return Err(ServerConfigError::InvalidValue)
+            }
+        };
+        let select = match table.try_parse_string("select"){
+            Ok(s) => Some(pointer(&s)),
+            Err(ServerConfigError::MissingKey) => None,
+            Err(e) => return Err(e)
+        };
+        let filters = match table.get("filter"){
+            Some(f) => as_tables(f)?.iter().map(Filter::try_parse).collect::<Result<_,_>>()?,
+            None => vec![]
+        };
+        let sort = match table.get("sort"){
+            Some(s) => as_tables(s)?.iter().map(SortKey::try_parse).collect::<Result<_,_>>()?,
+            None => vec![]
+        };
+        let fields = match table.get("fields"){
+            Some(f) => match f.clone().into_table(){
+                Ok(map) => {
+                    let mut fields = vec![];
+                    for (key,val) in map.into_iter(){
+                        match val.into_string(){
+                            Ok(source) => fields.push((key,pointer(&source))),
+                            Err(_) => return Err(ServerConfigError::InvalidValue)
+                        }
+                    }
+                    fields.sort_by(|a,b| a.0.cmp(&b.0));
+                    Some(fields)
+                },
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Err(ServerConfigError::InvalidValue)
+                }
+            },
+            None => None
+        };
+        Ok(Transform{ select, filters, sort, fields })
+    }
+    fn project(&self, item: &Value) -> Value{
+        match &self.fields{
+            Some(fields) => {
+                let mut map = Map::new();
+                for (key,source) in fields.iter(){
+                    map.insert(key.clone(),item.pointer(source).cloned().unwrap_or(Value::Null));
+                }
+                Value::Object(map)
+            },
+            None => item.clone()
+        }
+    }
+    // Selection runs first, then filtering, sorting and finally projection of each item
+    pub fn apply(&self, input: Value) -> Value{
+        let selected = match &self.select{
+            Some(p) => input.pointer(p).cloned().unwrap_or(Value::Null),
+            None => input
+        };
+        match selected{
+            Value::Array(list) => {
+                let mut items : Vec<Value> = list.into_iter()
+                    .filter(|item| self.filters.iter().all(|f| f.matches(item)))
+                    .collect();
+                if !self.sort.is_empty(){
+                    items.sort_by(|a,b| {
+                        for key in self.sort.iter(){
+                            let ordering = match (a.pointer(&key.field),b.pointer(&key.field)){
+                                (Some(x),Some(y)) => compare(x,y).unwrap_or(Ordering::Equal),
+                                (Some(_),None) => Ordering::Less,
+                                (None,Some(_)) => Ordering::Greater,
+                                (None,None) => Ordering::Equal
+                            };
+                            let ordering = match key.descending{
+                                true => ordering.reverse(),
+                                false => ordering
+                            };
+                            if ordering != Ordering::Equal{
+                                return ordering
+                            }
+                        }
+                        Ordering::Equal
+                    });
+                }
+                Value::Array(items.iter().map(|item| self.project(item)).collect())
+            },
+            other => self.project(&other)
+        }
+    }
+}
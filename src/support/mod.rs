@@ -1,5 +1,8 @@
 mod tokiort;
 pub mod cryptea;
+pub mod secrets;
+pub mod rekey;
 pub mod serialport;
+pub mod singleflight;
 #[allow(unused)]
 pub use tokiort::{TokioExecutor, TokioIo, TokioTimer};
\ No newline at end of file
@@ -0,0 +1,104 @@
+#![deny(warnings)]
+use std::io::Write;
+use std::path::Path;
+use toml_edit::{DocumentMut,Item,TableLike,Value};
+use super::secrets::{SecretKey,SecretError};
+
+#[derive(Debug)]
+pub enum RekeyError{
+    InvalidDocument(String),
+    Secret(String,SecretError)
+}
+
+impl std::fmt::Display for RekeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self{
+            RekeyError::InvalidDocument(e) => write!(f, "Settings could not be parsed: {e}"),
+            RekeyError::Secret(name,e) => write!(f, "Credentials of '{name}' could not be re-encrypted: {e}")
+        }
+    }
+}
+
+fn is_plain(table: &dyn TableLike, key: &str) -> bool{
+    table.get(key).and_then(|m| m.as_str()) == Some("plain")
+}
+
+// The encoded secret of a credentials table, inline values in plain mode are left as they are
+fn secret_key_of(credentials: &dyn TableLike) -> Option<&'static str>{
+    match credentials.get("mode").and_then(|m| m.as_str()){
+        Some("oauth2") if !is_plain(credentials,"secret_mode") => Some("client_secret"),
+        Some("oauth2") => None,
+        _ if is_plain(credentials,"mode") => None,
+        _ => Some("value")
+    }
+}
+
+// Replaces the value but keeps its surrounding whitespace and comments
+fn replace_keeping_decor(item: &mut Item, text: String){
+    if let Some(value) = item.as_value_mut(){
+        let decor = value.decor().clone();
+        *value = Value::from(text);
+        *value.decor_mut() = decor;
+    }
+}
+
+// Re-encrypts every encoded credentials value of the remote resources, returns the document and the count.
+// Nothing is changed unless all values decode with the old key.
+pub fn rekey_document(input: &str, old: &SecretKey, new: &SecretKey) -> Result<(String,usize),RekeyError>{
+    let mut document = match input.parse::<DocumentMut>(){
+        Ok(d) => d,
+        Err(e) => return Err(RekeyError::InvalidDocument(e.to_string()))
+    };
+    let resources = match document.get_mut("remote_resources").and_then(|r| r.as_table_like_mut()){
+        Some(r) => r,
+        None => return Ok((document.to_string(),0))
+    };
+    let mut count = 0;
+    for (name,resource) in resources.iter_mut(){
+        let credentials = match resource.as_table_like_mut().and_then(|r| r.get_mut("credentials")).and_then(|c| c.as_table_like_mut()){
+            Some(c) => c,
+            None => continue
+        };
+        let key = match secret_key_of(credentials){
+            Some(k) => k,
+            None => continue
+        };
+        let item = match credentials.get_mut(key){
+            Some(item) => item,
+            None => continue
+        };
+        let current = match item.as_str(){
+            Some(s) => s.to_string(),
+            None => continue
+        };
+        let rekeyed = old.decrypt(current.as_bytes())
+            .and_then(|plain| new.encrypt(&plain))
+            .map_err(|e| RekeyError::Secret(name.get().to_string(),e))?;
+        replace_keeping_decor(item,rekeyed);
+        count += 1;
+    }
+    Ok((document.to_string(),count))
+}
+
+// Written next to the original first so that an interrupted write never truncates the settings.
+// The copy is private until it gets the permissions of the original, right before it replaces it.
+pub fn replace_file(path: &Path, contents: &str) -> std::io::Result<()>{
+    let permissions = std::fs::metadata(path)?.permissions();
+    let temporary = path.with_extension("rekey");
+    let _ = std::fs::remove_file(&temporary);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options.open(&temporary)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|_| std::fs::set_permissions(&temporary,permissions))
+        .and_then(|_| std::fs::rename(&temporary,path));
+    if written.is_err(){
+        let _ = std::fs::remove_file(&temporary);
+    }
+    written
+}
@@ -0,0 +1,198 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc,Mutex};
+use argon2::{Algorithm,Argon2,Params,Version};
+use base64::{Engine,engine::general_purpose};
+use chacha20poly1305::{ChaCha20Poly1305,Key,Nonce};
+use chacha20poly1305::aead::{Aead,AeadCore,KeyInit,OsRng,Payload,rand_core::RngCore};
+use super::cryptea;
+
+// Secrets are stored as "<version tag><base64 payload>", untagged values are legacy XXTEA
+const CURRENT_TAG : &str = "v2:";
+const LEGACY_TAG : &str = "v1:";
+const SALT_LEN : usize = 16;
+const NONCE_LEN : usize = 12;
+const ASSOCIATED_DATA : &[u8] = b"ruddle-secret-v2";
+
+#[derive(Debug)]
+pub enum SecretError{
+    NoKey,
+    InvalidFormat,
+    EncryptFailed,
+    DecryptFailed,
+    KeySource(String),
+    Legacy(cryptea::CrypTeaError)
+}
+
+impl std::fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self{
+            SecretError::NoKey => write!(f, "No secret key is configured, set [secrets] key_env or key_file"),
+            SecretError::InvalidFormat => write!(f, "Secret is not in a known format"),
+            SecretError::EncryptFailed => write!(f, "Secret could not be encrypted"),
+            SecretError::DecryptFailed => write!(f, "Secret could not be decrypted, the key is wrong or the value was modified"),
+            SecretError::KeySource(e) => write!(f, "Secret key could not be read: {e}"),
+            SecretError::Legacy(e) => write!(f, "Legacy secret could not be decoded: {e}")
+        }
+    }
+}
+
+// Key material for the current format and the XXTEA key of legacy values
+#[derive(Debug)]
+pub struct SecretKey{
+    material: Option<Vec<u8>>,
+    legacy_key: String,
+    // Argon2 is slow by design, so keys derived for each salt are kept
+    derived: Mutex<HashMap<[u8;SALT_LEN],[u8;32]>>
+}
+
+impl SecretKey{
+    pub fn new(material: &[u8]) -> Self{
+        SecretKey{ material: Some(material.to_vec()), legacy_key: crate::OBFUSCATION_KEY.to_string(), derived: Mutex::new(HashMap::new()) }
+    }
+    pub fn legacy_only() -> Self{
+        SecretKey{ material: None, legacy_key: crate::OBFUSCATION_KEY.to_string(), derived: Mutex::new(HashMap::new()) }
+    }
+    pub fn with_legacy_key(mut self, key: &str) -> Self{
+        self.legacy_key = key.to_string();
+        self
+    }
+    pub fn from_env(name: &str) -> Result<Self,SecretError>{
+        read_env(name).map(|value| SecretKey::new(value.as_bytes()))
+    }
+    pub fn from_file(path: &Path) -> Result<Self,SecretError>{
+        read_file(path).map(|bytes| SecretKey::new(&bytes))
+    }
+    pub fn with_legacy_key_from_env(self, name: &str) -> Result<Self,SecretError>{
+        read_env(name).map(|value| self.with_legacy_key(&value))
+    }
+    pub fn with_legacy_key_from_file(self, path: &Path) -> Result<Self,SecretError>{
+        match String::from_utf8(read_file(path)?){
+            Ok(value) => Ok(self.with_legacy_key(&value)),
+            Err(_) => Err(SecretError::KeySource(format!("{} is not valid UTF-8",path.display())))
+        }
+    }
+    fn derive(&self, salt: &[u8;SALT_LEN]) -> Result<Key,SecretError>{
+        let material = match &self.material{
+            Some(m) => m,
+            None => return Err(SecretError::NoKey)
+        };
+        if let Some(key) = self.derived.lock().ok().and_then(|cache| cache.get(salt).copied()){
+            return Ok(Key::from(key))
+        }
+        let params = match Params::new(19 * 1024, 2, 1, Some(32)){
+            Ok(p) => p,
+            Err(_) => return Err(SecretError::InvalidFormat)
+        };
+        let mut key = [0u8;32];
+        if Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(material, salt, &mut key).is_err(){
+            return Err(SecretError::InvalidFormat)
+        }
+        if let Ok(mut cache) = self.derived.lock(){
+            cache.insert(*salt,key);
+        }
+        Ok(Key::from(key))
+    }
+    pub fn encrypt(&self, plaintext: &str) -> Result<String,SecretError>{
+        let mut salt = [0u8;SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = ChaCha20Poly1305::new(&self.derive(&salt)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = match cipher.encrypt(&nonce, Payload{ msg: plaintext.as_bytes(), aad: ASSOCIATED_DATA }){
+            Ok(bytes) => bytes,
+            Err(_) => return Err(SecretError::EncryptFailed)
+        };
+        let mut payload = Vec::with_capacity(SALT_LEN + NONCE_LEN + sealed.len());
+        payload.extend_from_slice(&salt);
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&sealed);
+        Ok(format!("{CURRENT_TAG}{}",general_purpose::STANDARD.encode(payload)))
+    }
+    // Argon2 is too slow for the request path, so the key of an encrypted value is derived on the
+    // blocking pool and decrypt finds it cached
+    pub async fn prepare(self: &Arc<Self>, input: &[u8]){
+        let salt = match salt_of(input){
+            Some(s) => s,
+            None => return
+        };
+        let derived = self.derived.lock().is_ok_and(|cache| cache.contains_key(&salt));
+        if derived || self.material.is_none(){
+            return
+        }
+        let key = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || key.derive(&salt)).await{
+            eprintln!("{e}");
+        }
+    }
+    pub fn decrypt(&self, input: &[u8]) -> Result<String,SecretError>{
+        let encoded = match input.strip_prefix(CURRENT_TAG.as_bytes()){
+            Some(rest) => rest,
+            None => return self.decode_legacy(input)
+        };
+        let payload = match general_purpose::STANDARD.decode(encoded){
+            Ok(bytes) if bytes.len() > SALT_LEN + NONCE_LEN => bytes,
+            _ => return Err(SecretError::InvalidFormat)
+        };
+        let (salt,rest) = payload.split_at(SALT_LEN);
+        let (nonce,sealed) = rest.split_at(NONCE_LEN);
+        let salt : [u8;SALT_LEN] = match salt.try_into(){
+            Ok(s) => s,
+            Err(_) => return Err(SecretError::InvalidFormat)
+        };
+        let cipher = ChaCha20Poly1305::new(&self.derive(&salt)?);
+        match cipher.decrypt(Nonce::from_slice(nonce), Payload{ msg: sealed, aad: ASSOCIATED_DATA }){
+            Ok(plain) => match String::from_utf8(plain){
+                Ok(s) => Ok(s),
+                Err(_) => Err(SecretError::InvalidFormat)
+            },
+            Err(_) => Err(SecretError::DecryptFailed)
+        }
+    }
+    pub fn encrypt_legacy(&self, plaintext: &str) -> Result<String,SecretError>{
+        match cryptea::encode_as_base64(plaintext,&self.legacy_key){
+            Ok(s) => Ok(s),
+            Err(_) => Err(SecretError::EncryptFailed)
+        }
+    }
+    // Legacy values may be tagged with "v1:" or have no tag at all
+    fn decode_legacy(&self, input: &[u8]) -> Result<String,SecretError>{
+        let encoded = input.strip_prefix(LEGACY_TAG.as_bytes()).unwrap_or(input);
+        match cryptea::decode(&encoded.to_vec(),&self.legacy_key){
+            Ok(s) => Ok(s),
+            Err(e) => Err(SecretError::Legacy(e))
+        }
+    }
+}
+
+fn salt_of(input: &[u8]) -> Option<[u8;SALT_LEN]>{
+    let encoded = input.strip_prefix(CURRENT_TAG.as_bytes())?;
+    match general_purpose::STANDARD.decode(encoded){
+        Ok(bytes) if bytes.len() > SALT_LEN + NONCE_LEN => bytes[..SALT_LEN].try_into().ok(),
+        _ => None
+    }
+}
+
+fn read_env(name: &str) -> Result<String,SecretError>{
+    match std::env::var(name){
+        Ok(value) if !value.is_empty() => Ok(value),
+        Ok(_) => Err(SecretError::KeySource(format!("environment variable {name} is empty"))),
+        Err(e) => Err(SecretError::KeySource(format!("{name}: {e}")))
+    }
+}
+
+// A trailing newline of the key file is not part of the key
+fn read_file(path: &Path) -> Result<Vec<u8>,SecretError>{
+    match std::fs::read(path){
+        Ok(mut bytes) => {
+            while bytes.last().is_some_and(|b| b.is_ascii_whitespace()){
+                bytes.pop();
+            }
+            match bytes.is_empty(){
+                true => Err(SecretError::KeySource(format!("{} is empty",path.display()))),
+                false => Ok(bytes)
+            }
+        },
+        Err(e) => Err(SecretError::KeySource(format!("{}: {e}",path.display())))
+    }
+}
@@ -0,0 +1,38 @@
+#![deny(warnings)]
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc,Mutex};
+use tokio::sync::OnceCell;
+
+// Deduplicates concurrent calls with the same key so that only one of them runs
+// and the rest await and share its result.
+#[derive(Debug)]
+pub struct SingleFlight<T: Clone>{
+    inflight: Mutex<HashMap<String,Arc<OnceCell<T>>>>
+}
+
+impl<T: Clone> SingleFlight<T>{
+    pub fn new() -> Self{
+        SingleFlight{ inflight: Mutex::new(HashMap::new()) }
+    }
+    pub async fn run<F>(&self, key: &str, task: F) -> T
+    where F: Future<Output = T>
+    {
+        let cell = match self.inflight.lock(){
+            Ok(mut map) => Some(map.entry(key.to_string()).or_insert_with(|| Arc::new(OnceCell::new())).clone()),
+            Err(_) => None
+        };
+        let cell = match cell{
+            Some(c) => c,
+            None => return task.await
+        };
+        // If the running call gets cancelled, one of the waiting callers runs its own task instead
+        let result = cell.get_or_init(|| task).await.clone();
+        if let Ok(mut map) = self.inflight.lock(){
+            if map.get(key).is_some_and(|current| Arc::ptr_eq(current,&cell)){
+                map.remove(key);
+            }
+        }
+        result
+    }
+}
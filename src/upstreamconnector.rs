@@ -0,0 +1,160 @@
+#![deny(warnings)]
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context,Poll};
+use hyper::Uri;
+use hyper::rt::{Read,ReadBufCursor,Write};
+use hyper_util::client::legacy::connect::{Connected,Connection,HttpConnector};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt,AsyncWriteExt};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use crate::settings::proxy::{Proxy,ProxySettings};
+use crate::settings::resolve::ResolveOverrides;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Larger CONNECT responses are not expected from a proxy
+const MAX_TUNNEL_RESPONSE : usize = 8192;
+
+#[derive(Debug)]
+pub enum TunnelError{
+    Closed,
+    ResponseTooLarge,
+    Refused(String)
+}
+
+impl std::fmt::Display for TunnelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self{
+            TunnelError::Closed => write!(f, "Proxy closed the connection during CONNECT"),
+            TunnelError::ResponseTooLarge => write!(f, "Proxy response to CONNECT is too large"),
+            TunnelError::Refused(status) => write!(f, "Proxy refused the tunnel: {status}")
+        }
+    }
+}
+
+impl std::error::Error for TunnelError{}
+
+// Connection to an upstream, either direct, tunnelled through a proxy or to a proxy that forwards plain HTTP
+pub struct UpstreamStream{
+    io: TokioIo<TcpStream>,
+    // Plain HTTP requests to a proxy are sent in absolute form
+    forwarding: bool
+}
+
+impl Connection for UpstreamStream{
+    fn connected(&self) -> Connected{
+        self.io.connected().proxy(self.forwarding)
+    }
+}
+
+impl Read for UpstreamStream{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<std::io::Result<()>>{
+        Pin::new(&mut self.io).poll_read(cx,buf)
+    }
+}
+
+impl Write for UpstreamStream{
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>>{
+        Pin::new(&mut self.io).poll_write(cx,buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>{
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+    fn is_write_vectored(&self) -> bool{
+        self.io.is_write_vectored()
+    }
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[std::io::IoSlice<'_>]) -> Poll<std::io::Result<usize>>{
+        Pin::new(&mut self.io).poll_write_vectored(cx,bufs)
+    }
+}
+
+// Asks the proxy to open a tunnel to the destination, TLS is then negotiated through it with the upstream itself
+async fn open_tunnel(io: TokioIo<TcpStream>, destination: &Uri, proxy: &Proxy) -> Result<TokioIo<TcpStream>,BoxError>{
+    let host = destination.host().unwrap_or_default();
+    let port = destination.port_u16().unwrap_or(443);
+    let mut head = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some(auth) = &proxy.authorization{
+        head.push_str(&format!("Proxy-Authorization: {}\r\n",auth.to_str()?));
+    }
+    head.push_str("\r\n");
+    let mut stream = io.into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    // Read byte by byte, anything past the end of the response already belongs to the TLS handshake
+    let mut response = Vec::with_capacity(128);
+    let mut byte = [0u8;1];
+    while !response.ends_with(b"\r\n\r\n"){
+        if response.len() >= MAX_TUNNEL_RESPONSE{
+            return Err(TunnelError::ResponseTooLarge.into())
+        }
+        match stream.read(&mut byte).await?{
+            0 => return Err(TunnelError::Closed.into()),
+            _ => response.push(byte[0])
+        }
+    }
+    let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string();
+    match status_line.split_whitespace().nth(1){
+        Some(code) if code.starts_with('2') => Ok(TokioIo::new(stream)),
+        _ => Err(TunnelError::Refused(status_line).into())
+    }
+}
+
+// Overridden hosts are connected to directly, a proxy would resolve the name on its own
+pub fn proxy_for<'a>(proxy: Option<&'a ProxySettings>, resolve: &ResolveOverrides, destination: &Uri) -> Option<&'a Proxy>{
+    match resolve.lookup(destination){
+        Some(_) => None,
+        None => proxy.and_then(|p| p.intercept(destination))
+    }
+}
+
+#[derive(Clone)]
+pub struct UpstreamConnector{
+    http: HttpConnector,
+    proxy: Option<Arc<ProxySettings>>,
+    resolve: Arc<ResolveOverrides>
+}
+
+impl UpstreamConnector{
+    pub fn new(http: HttpConnector, proxy: Option<Arc<ProxySettings>>, resolve: Arc<ResolveOverrides>) -> Self{
+        UpstreamConnector{ http, proxy, resolve }
+    }
+}
+
+impl Service<Uri> for UpstreamConnector{
+    type Response = UpstreamStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<UpstreamStream,BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(),Self::Error>>{
+        self.http.poll_ready(cx).map_err(|e| e.into())
+    }
+    fn call(&mut self, destination: Uri) -> Self::Future{
+        if let Some(address) = self.resolve.lookup(&destination){
+            let mut http = self.http.clone();
+            return Box::pin(async move {
+                let target = format!("http://{address}/").parse::<Uri>()?;
+                Ok(UpstreamStream{ io: http.call(target).await?, forwarding: false })
+            })
+        }
+        let proxy = proxy_for(self.proxy.as_deref(),&self.resolve,&destination).cloned();
+        let mut http = self.http.clone();
+        Box::pin(async move {
+            match proxy{
+                None => Ok(UpstreamStream{ io: http.call(destination).await?, forwarding: false }),
+                Some(proxy) => {
+                    let io = http.call(proxy.uri.clone()).await?;
+                    match destination.scheme_str(){
+                        Some("https") => Ok(UpstreamStream{ io: open_tunnel(io,&destination,&proxy).await?, forwarding: false }),
+                        _ => Ok(UpstreamStream{ io, forwarding: true })
+                    }
+                }
+            }
+        })
+    }
+}